@@ -0,0 +1,33 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Record which commit and when this binary was built, for /api/version
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    
+    // Honour SOURCE_DATE_EPOCH so reproducible builds stay reproducible
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0)
+        });
+    
+    println!("cargo:rustc-env=SERVE_FOLDER_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=SERVE_FOLDER_BUILT_AT={}", built_at);
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // include_dir! can't ask to be rebuilt when a file under web/ changes,
+    // so the build script asks for it
+    println!("cargo:rerun-if-changed=web");
+}
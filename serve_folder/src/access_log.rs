@@ -0,0 +1,200 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use futures_util::StreamExt;
+use serde::Serialize;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+// Rotated files kept next to the log: access.log.1 (newest) to .5
+const MAX_ROTATED_FILES: u32 = 5;
+
+// Buffered lines reach the file at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// One line of the access log, written as JSON
+#[derive(Serialize)]
+struct AccessRecord {
+    // Unix epoch millis when the response finished
+    time: u64,
+    client: Option<IpAddr>,
+    method: String,
+    path: String,
+    status: u16,
+    // Body bytes actually sent, after compression
+    bytes: u64,
+    duration_ms: u64,
+}
+
+// Appends request lines to --access-log. Requests only queue their line;
+// a background thread does the writing, so a slow disk never holds up a
+// response
+pub struct AccessLog {
+    sender: Sender<String>,
+}
+
+impl AccessLog {
+    pub fn open(path: &Path, max_size: Option<u64>) -> io::Result<Self> {
+        let writer = LogWriter::open(path.to_path_buf(), max_size)?;
+        let (sender, receiver) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            let mut writer = writer;
+            loop {
+                match receiver.recv_timeout(FLUSH_INTERVAL) {
+                    Ok(line) => {
+                        if let Err(e) = writer.write_line(&line) {
+                            eprintln!("Failed to write access log: {}", e);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        let _ = writer.flush();
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        let _ = writer.flush();
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self { sender })
+    }
+    
+    // Count the body as it's sent and log the request once it's done (or
+    // the client goes away), so `bytes` is what was actually transferred
+    pub fn record(&self, response: Response, client: Option<IpAddr>, method: String, path: String, started: Instant) -> Response {
+        let (parts, body) = response.into_parts();
+        let mut pending = PendingRecord {
+            sender: self.sender.clone(),
+            started,
+            record: AccessRecord {
+                time: 0,
+                client,
+                method,
+                path,
+                status: parts.status.as_u16(),
+                bytes: 0,
+                duration_ms: 0,
+            },
+        };
+        let body = body.map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                pending.add_bytes(bytes.len());
+            }
+            chunk
+        });
+        Response::from_parts(parts, Body::wrap_stream(body))
+    }
+}
+
+// A request whose line is written when its body is dropped
+struct PendingRecord {
+    sender: Sender<String>,
+    started: Instant,
+    record: AccessRecord,
+}
+
+impl PendingRecord {
+    // A method rather than a field update, so the body closure captures
+    // (and drops) the whole record
+    fn add_bytes(&mut self, len: usize) {
+        self.record.bytes += len as u64;
+    }
+}
+
+impl Drop for PendingRecord {
+    fn drop(&mut self) {
+        self.record.time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+        self.record.duration_ms = self.started.elapsed().as_millis() as u64;
+        if let Ok(line) = serde_json::to_string(&self.record) {
+            // The writer thread only goes away with the process
+            let _ = self.sender.send(line);
+        }
+    }
+}
+
+struct LogWriter {
+    path: PathBuf,
+    max_size: Option<u64>,
+    file: BufWriter<File>,
+    // Bytes in the current file, including those still buffered
+    size: u64,
+}
+
+impl LogWriter {
+    fn open(path: PathBuf, max_size: Option<u64>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+    
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + len > max_size {
+                self.rotate()?;
+            }
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+    
+    // Shift access.log.N up by one, dropping the oldest, move the current
+    // file to .1 and start a fresh one
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for n in (1..MAX_ROTATED_FILES).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn rotates_when_the_file_would_grow_past_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut writer = LogWriter::open(path.clone(), Some(10)).unwrap();
+        
+        writer.write_line("aaaa").unwrap();
+        writer.write_line("bbbb").unwrap();
+        writer.write_line("cccc").unwrap();
+        writer.flush().unwrap();
+        
+        assert_eq!(fs::read_to_string(rotated_path(&path, 1)).unwrap(), "aaaa\nbbbb\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cccc\n");
+    }
+}
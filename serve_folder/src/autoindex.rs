@@ -0,0 +1,109 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::models::{FileEntry, SortKey};
+use crate::paths::RAW_PATH_PREFIX;
+
+// Characters escaped in a single path segment of a link
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'`').add(b'{').add(b'}').add(b'/').add(b'\\');
+
+// Render a JS-free directory page in the style of a classic autoindex.
+// `current_path` is relative to the root; links are relative to the
+// directory, whose URL always ends in `/`
+pub fn render_autoindex(base_path: &str, current_path: &str, entries: &[FileEntry], sort: SortKey, descending: bool) -> String {
+    let title = format!("Index of /{}", current_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
+         <title>{title}</title>\n<style>\n\
+         body {{ font-family: sans-serif; margin: 1em 2em; }}\n\
+         table {{ border-collapse: collapse; }}\n\
+         th, td {{ padding: 2px 1.5em 2px 0; text-align: left; }}\n\
+         td.size {{ text-align: right; }}\n\
+         </style>\n</head>\n<body>\n<h1>{title}</h1>\n<table>\n<tr>",
+        title = escape_html(&title)
+    );
+    
+    for (key, label) in [(SortKey::Name, "Name"), (SortKey::Modified, "Last modified"), (SortKey::Size, "Size")] {
+        // Clicking the active column flips its order
+        let order = if key == sort && !descending { "desc" } else { "asc" };
+        html.push_str(&format!("<th><a href=\"?sort={}&amp;order={}\">{}</a></th>", sort_name(key), order, label));
+    }
+    html.push_str("</tr>\n");
+    
+    if !current_path.is_empty() {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td class=\"size\">-</td></tr>\n");
+    }
+    
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        // Raw paths are already percent-encoded bytes; the name is lossy
+        let href = match entry.path.strip_prefix(RAW_PATH_PREFIX) {
+            Some(encoded) => format!("{}{}", encoded.rsplit('/').next().unwrap_or(encoded), suffix),
+            None => format!("{}{}", utf8_percent_encode(&entry.name, SEGMENT), suffix),
+        };
+        let size = if entry.is_dir { "-".to_string() } else { format_size(entry.size) };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td class=\"size\">{}</td></tr>\n",
+            escape_html(&href),
+            escape_html(&entry.name),
+            suffix,
+            entry.modified.map(format_modified).unwrap_or_default(),
+            size
+        ));
+    }
+    
+    html.push_str(&format!(
+        "</table>\n<p><a href=\"{}/webui\">Open the web UI</a></p>\n</body>\n</html>\n",
+        escape_html(base_path)
+    ));
+    html
+}
+
+fn sort_name(key: SortKey) -> &'static str {
+    match key {
+        SortKey::Name => "name",
+        SortKey::Size => "size",
+        SortKey::Modified => "modified",
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#039;")
+}
+
+// Sizes as the web UI shows them, e.g. "1.5 MB"
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// Epoch millis as "YYYY-MM-DD HH:MM" in UTC
+fn format_modified(millis: u64) -> String {
+    match time::OffsetDateTime::from_unix_timestamp((millis / 1000) as i64) {
+        Ok(time) => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            time.year(),
+            u8::from(time.month()),
+            time.day(),
+            time.hour(),
+            time.minute()
+        ),
+        Err(_) => String::new(),
+    }
+}
@@ -0,0 +1,110 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use qrcode::QrCode;
+use qrcode::render::unicode;
+
+// Interface name prefixes of container/VM bridges that other devices
+// on the network can't reach
+const VIRTUAL_INTERFACE_PREFIXES: &[&str] = &[
+    "docker", "br-", "veth", "virbr", "vmnet", "vboxnet", "vEthernet", "VirtualBox", "VMware",
+];
+
+// Print where the server can be reached, optionally with a QR code
+// of the first LAN address for scanning from a phone
+pub fn print_banner(addr: SocketAddr, base_path: &str, show_qr: bool) {
+    println!("Serving on:");
+    if !addr.ip().is_unspecified() && !addr.ip().is_loopback() {
+        // Bound to one interface, so that's the only address that answers
+        let url = web_ui_url(addr.ip(), addr.port(), base_path);
+        println!("  Network: {}", url);
+        if show_qr {
+            print_qr(&url);
+        }
+        println!("Press Ctrl+C to stop the server");
+        return;
+    }
+    
+    println!("  Local:   {}", local_url(addr, base_path));
+    
+    let lan_urls: Vec<String> = if addr.ip().is_loopback() {
+        Vec::new()
+    } else {
+        lan_addresses(addr.ip())
+            .into_iter()
+            .map(|ip| web_ui_url(ip, addr.port(), base_path))
+            .collect()
+    };
+    for url in &lan_urls {
+        println!("  Network: {}", url);
+    }
+    
+    if show_qr {
+        match lan_urls.first() {
+            Some(url) => print_qr(url),
+            None => println!("No network address found for a QR code"),
+        }
+    }
+    
+    println!("Press Ctrl+C to stop the server");
+}
+
+// The web UI as reached from this machine
+pub fn local_url(addr: SocketAddr, base_path: &str) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    web_ui_url(ip, addr.port(), base_path)
+}
+
+// IPv6 hosts need brackets in a URL, which SocketAddr's Display adds
+fn web_ui_url(ip: IpAddr, port: u16, base_path: &str) -> String {
+    format!("http://{}{}/webui", SocketAddr::new(ip, port), base_path)
+}
+
+// Addresses of interfaces that are up and reachable from other machines.
+// 0.0.0.0 only answers on IPv4. :: answers on IPv6 and, where the OS makes
+// it dual-stack (Linux and macOS by default, not Windows), on IPv4 too
+fn lan_addresses(bound: IpAddr) -> Vec<IpAddr> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(_) => return Vec::new(),
+    };
+    let include_ipv4 = bound.is_ipv4() || cfg!(not(windows));
+    
+    let mut addresses: Vec<IpAddr> = interfaces.iter()
+        .filter(|iface| iface.is_oper_up() && !iface.is_loopback() && !iface.is_link_local())
+        .filter(|iface| !VIRTUAL_INTERFACE_PREFIXES.iter().any(|prefix| iface.name.starts_with(prefix)))
+        .map(|iface| iface.ip())
+        .filter(|ip| if ip.is_ipv4() { include_ipv4 } else { bound.is_ipv6() })
+        .collect();
+    // IPv4 first so the QR code gets the address phones most likely reach
+    addresses.sort_by_key(|ip| ip.is_ipv6());
+    addresses.dedup();
+    addresses
+}
+
+fn print_qr(url: &str) {
+    match QrCode::new(url) {
+        Ok(code) => {
+            let image = code.render::<unicode::Dense1x2>()
+                .dark_color(unicode::Dense1x2::Light)
+                .light_color(unicode::Dense1x2::Dark)
+                .build();
+            println!("{}", image);
+        }
+        Err(e) => eprintln!("Failed to build QR code: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn ipv6_urls_are_bracketed() {
+        assert_eq!(local_url("[::]:8080".parse().unwrap(), ""), "http://[::1]:8080/webui");
+        assert_eq!(local_url("0.0.0.0:8080".parse().unwrap(), "/files"), "http://127.0.0.1:8080/files/webui");
+        assert_eq!(web_ui_url("fd00::2".parse().unwrap(), 9000, ""), "http://[fd00::2]:9000/webui");
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Metadata;
+use std::hash::{Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::Filter;
+use warp::http::{HeaderValue, StatusCode};
+use warp::http::header::{CACHE_CONTROL, ETAG, LAST_MODIFIED};
+use warp::reply::Response;
+
+// The conditional request headers a client sent
+#[derive(Clone, Debug, Default)]
+pub struct Conditionals {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<String>,
+}
+
+pub fn conditionals() -> impl Filter<Extract = (Conditionals,), Error = std::convert::Infallible> + Clone {
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .map(|if_none_match, if_modified_since| Conditionals {
+            if_none_match,
+            if_modified_since,
+        })
+        .or(warp::any().map(Conditionals::default))
+        .unify()
+}
+
+// Cache validators for one representation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    // Files on disk are tagged by size and modification time, which
+    // changes whenever the content does without having to read it
+    pub fn for_file(metadata: &Metadata) -> Self {
+        let modified = metadata.modified().ok();
+        let mtime = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        Self {
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), mtime),
+            last_modified: modified,
+        }
+    }
+    
+    // Embedded assets only change with a new build, so hash the content
+    pub fn for_embedded(body: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        Self {
+            etag: format!("\"{}-{:x}\"", env!("CARGO_PKG_VERSION"), hasher.finish()),
+            last_modified: None,
+        }
+    }
+    
+    // Whether the client's cached copy is still current. If-None-Match
+    // wins over If-Modified-Since when both are present.
+    pub fn is_not_modified(&self, conditionals: &Conditionals) -> bool {
+        if let Some(if_none_match) = &conditionals.if_none_match {
+            return etag_matches(if_none_match, &self.etag);
+        }
+        
+        match (&conditionals.if_modified_since, self.last_modified) {
+            (Some(since), Some(modified)) => match httpdate::parse_http_date(since) {
+                // HTTP dates have one-second resolution
+                Ok(since) => truncate_to_secs(modified) <= since,
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+    
+    // Add ETag and Last-Modified to a response
+    pub fn apply(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(modified) = self.last_modified {
+            if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(modified)) {
+                headers.insert(LAST_MODIFIED, value);
+            }
+        }
+    }
+    
+    // Whether an If-Range validator still names this representation, so
+    // the range may be served. ETags compare strongly; a date must equal
+    // Last-Modified exactly
+    pub fn matches_if_range(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return !self.etag.starts_with("W/") && if_range == self.etag;
+        }
+        match (httpdate::parse_http_date(if_range), self.last_modified) {
+            (Ok(date), Some(modified)) => truncate_to_secs(modified) == date,
+            _ => false,
+        }
+    }
+    
+    pub fn not_modified(&self) -> Response {
+        let mut response = Response::default();
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        self.apply(&mut response);
+        response
+    }
+}
+
+// Let browsers keep a copy but check back before using it
+pub fn revalidate(response: &mut Response) {
+    response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+}
+
+// For assets whose URL changes with their content, so a cached copy
+// never needs checking
+pub fn cache_forever(response: &mut Response) {
+    response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("public, max-age=31536000, immutable"));
+}
+
+// Weak comparison of an If-None-Match list against our tag
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + std::time::Duration::from_secs(duration.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"10-abc\"".to_string(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)),
+        }
+    }
+    
+    fn with_etag(value: &str) -> Conditionals {
+        Conditionals {
+            if_none_match: Some(value.to_string()),
+            ..Default::default()
+        }
+    }
+    
+    fn with_date(value: &str) -> Conditionals {
+        Conditionals {
+            if_modified_since: Some(value.to_string()),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let validators = validators();
+        assert!(validators.is_not_modified(&with_etag("\"10-abc\"")));
+        assert!(validators.is_not_modified(&with_etag("W/\"10-abc\"")));
+        assert!(validators.is_not_modified(&with_etag("\"other\", \"10-abc\"")));
+        assert!(validators.is_not_modified(&with_etag("*")));
+        assert!(!validators.is_not_modified(&with_etag("\"10-abd\"")));
+    }
+    
+    #[test]
+    fn if_range_needs_a_strong_match() {
+        let validators = validators();
+        assert!(validators.matches_if_range("\"10-abc\""));
+        assert!(!validators.matches_if_range("W/\"10-abc\""));
+        assert!(!validators.matches_if_range("\"10-abd\""));
+        assert!(validators.matches_if_range("Tue, 14 Nov 2023 22:13:20 GMT"));
+        assert!(!validators.matches_if_range("Tue, 14 Nov 2023 22:13:21 GMT"));
+    }
+    
+    #[test]
+    fn modified_since_compares_whole_seconds() {
+        let validators = validators();
+        let same_second = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let earlier = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(1_699_999_999));
+        assert!(validators.is_not_modified(&with_date(&same_second)));
+        assert!(!validators.is_not_modified(&with_date(&earlier)));
+        assert!(!validators.is_not_modified(&with_date("not a date")));
+    }
+    
+    #[test]
+    fn etag_takes_precedence_over_date() {
+        let validators = validators();
+        let conditionals = Conditionals {
+            if_none_match: Some("\"stale\"".to_string()),
+            if_modified_since: Some(httpdate::fmt_http_date(SystemTime::now())),
+        };
+        assert!(!validators.is_not_modified(&conditionals));
+    }
+    
+    #[test]
+    fn no_conditionals_means_modified() {
+        assert!(!validators().is_not_modified(&Conditionals::default()));
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::models::ZipProgress;
+use crate::state::ServerState;
+
+// Same cadence as the archive progress trackers
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "md5" => Some(ChecksumAlgorithm::Md5),
+            "sha256" => Some(ChecksumAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+// Hex digest of a file, read in chunks. With an operation id, progress
+// is published through the same state entries ZIP downloads use.
+pub fn hash_file(
+    path: &Path,
+    algorithm: ChecksumAlgorithm,
+    progress: Option<(&ServerState, &str)>,
+) -> io::Result<String> {
+    match algorithm {
+        ChecksumAlgorithm::Md5 => digest_file::<Md5>(path, progress),
+        ChecksumAlgorithm::Sha256 => digest_file::<Sha256>(path, progress),
+    }
+}
+
+fn digest_file<D: Digest>(path: &Path, progress: Option<(&ServerState, &str)>) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    
+    let mut hasher = D::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut read_total = 0u64;
+    let mut last_update = Instant::now();
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        read_total += read as u64;
+        
+        if let Some((state, operation_id)) = progress {
+            if last_update.elapsed() >= PROGRESS_INTERVAL {
+                report_progress(state, operation_id, &name, read_total, total);
+                last_update = Instant::now();
+            }
+        }
+    }
+    
+    if let Some((state, operation_id)) = progress {
+        report_progress(state, operation_id, &name, total, total);
+    }
+    
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn report_progress(state: &ServerState, operation_id: &str, name: &str, read: u64, total: u64) {
+    let percentage = if total > 0 {
+        (read as f64 / total as f64 * 100.0) as f32
+    } else {
+        100.0
+    };
+    
+    state.update_progress(operation_id, ZipProgress {
+        current_file: name.to_string(),
+        processed_files: if read >= total { 1 } else { 0 },
+        total_files: 1,
+        percentage,
+        ..Default::default()
+    });
+}
@@ -0,0 +1,41 @@
+use std::net::{IpAddr, SocketAddr};
+use warp::Filter;
+
+// The address a request came from. Behind a reverse proxy (--trust-proxy)
+// that's the first X-Forwarded-For entry, which the proxy set; otherwise
+// the header is ignored, since any client could send it. None when the
+// connection has no peer address
+pub fn client_ip(remote: Option<SocketAddr>, forwarded_for: Option<&str>, trust_proxy: bool) -> Option<IpAddr> {
+    if trust_proxy {
+        let forwarded = forwarded_for
+            .and_then(|header| header.split(',').next())
+            .and_then(|first| first.trim().parse::<IpAddr>().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    remote.map(|addr| addr.ip())
+}
+
+// Extract the client address for handlers and logging
+pub fn with_client_ip(trust_proxy: bool) -> impl Filter<Extract = (Option<IpAddr>,), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(move |remote, forwarded_for: Option<String>| client_ip(remote, forwarded_for.as_deref(), trust_proxy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn forwarded_for_is_only_used_behind_a_proxy() {
+        let remote = Some(SocketAddr::from(([10, 0, 0, 1], 4000)));
+        let header = Some("203.0.113.7, 10.0.0.1");
+        
+        assert_eq!(client_ip(remote, header, false), Some(IpAddr::from([10, 0, 0, 1])));
+        assert_eq!(client_ip(remote, header, true), Some(IpAddr::from([203, 0, 113, 7])));
+        assert_eq!(client_ip(remote, Some("garbage"), true), Some(IpAddr::from([10, 0, 0, 1])));
+        assert_eq!(client_ip(None, None, true), None);
+    }
+}
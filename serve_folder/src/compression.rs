@@ -0,0 +1,160 @@
+use std::io;
+use async_compression::tokio::bufread::{GzipEncoder, ZlibEncoder};
+use futures_util::TryStreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+use warp::http::{HeaderValue, StatusCode};
+use warp::http::header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY};
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::Reply;
+
+// Bodies smaller than this aren't worth the compression overhead
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// Compress eligible responses on the fly according to Accept-Encoding
+pub fn compress_reply(reply: impl Reply, accept_encoding: Option<String>, enabled: bool) -> Response {
+    let mut response = reply.into_response();
+    if !enabled || !is_compressible(&response) {
+        return response;
+    }
+    
+    // The body varies by Accept-Encoding even when we don't compress this time
+    response.headers_mut().append(VARY, HeaderValue::from_static("accept-encoding"));
+    
+    let encoding = match accept_encoding.as_deref().and_then(preferred_encoding) {
+        Some(encoding) => encoding,
+        None => return response,
+    };
+    
+    let (mut parts, body) = response.into_parts();
+    let reader = StreamReader::new(body.map_err(|e| io::Error::other(e.to_string())));
+    let body = match encoding {
+        Encoding::Gzip => Body::wrap_stream(ReaderStream::new(GzipEncoder::new(reader))),
+        // HTTP "deflate" is the zlib format, not raw deflate
+        Encoding::Deflate => Body::wrap_stream(ReaderStream::new(ZlibEncoder::new(reader))),
+    };
+    
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    // Byte offsets and a strong ETag describe the identity body, so a
+    // resumed download would splice identity bytes onto compressed ones.
+    // A weak ETag still answers If-None-Match but never satisfies If-Range
+    parts.headers.remove(ACCEPT_RANGES);
+    if let Some(weak_etag) = parts.headers.get(ETAG).and_then(weak_etag) {
+        parts.headers.insert(ETAG, weak_etag);
+    }
+    Response::from_parts(parts, body)
+}
+
+// The weak form of a strong ETag, or None if it's already weak
+fn weak_etag(etag: &HeaderValue) -> Option<HeaderValue> {
+    let etag = etag.to_str().ok()?;
+    if etag.starts_with("W/") {
+        return None;
+    }
+    HeaderValue::from_str(&format!("W/{}", etag)).ok()
+}
+
+// Only full, not-yet-encoded text-like bodies of a useful size are compressed;
+// archives, images and media are already compressed
+fn is_compressible(response: &Response) -> bool {
+    if response.status() != StatusCode::OK || response.headers().contains_key(CONTENT_ENCODING) {
+        return false;
+    }
+    
+    let headers = response.headers();
+    let small = headers.get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(|len| len < MIN_COMPRESS_SIZE)
+        .unwrap_or(false);
+    if small {
+        return false;
+    }
+    
+    let content_type = match headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()) {
+        Some(content_type) => content_type,
+        None => return false,
+    };
+    // Event streams must reach the client as they're sent, not buffered
+    if content_type.starts_with("text/event-stream") {
+        return false;
+    }
+    content_type.starts_with("text/")
+        || content_type.contains("json")
+        || content_type.contains("javascript")
+        || content_type.contains("xml")
+        || content_type.starts_with("application/wasm")
+}
+
+// Pick gzip over deflate when both are accepted; honor `q=0` refusals
+fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut accepted = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let refused = parts.any(|param| {
+            let param = param.trim().replace(' ', "");
+            param == "q=0" || param == "q=0.0" || param == "q=0.00" || param == "q=0.000"
+        });
+        if !refused {
+            accepted.push(name);
+        }
+    }
+    
+    if accepted.iter().any(|name| name == "gzip" || name == "*") {
+        Some(Encoding::Gzip)
+    } else if accepted.iter().any(|name| name == "deflate") {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn text_response() -> Response {
+        let mut response = Response::new(Body::from("text ".repeat(500)));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(2500));
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(ETAG, HeaderValue::from_static("\"9c4-abc\""));
+        response
+    }
+    
+    #[test]
+    fn compressed_bodies_drop_ranges_and_weaken_the_etag() {
+        let response = compress_reply(text_response(), Some("gzip".to_string()), true);
+        let headers = response.headers();
+        assert_eq!(headers[CONTENT_ENCODING], "gzip");
+        assert!(!headers.contains_key(ACCEPT_RANGES));
+        assert_eq!(headers[ETAG], "W/\"9c4-abc\"");
+    }
+    
+    #[test]
+    fn identity_bodies_keep_ranges_and_the_strong_etag() {
+        let response = compress_reply(text_response(), None, true);
+        let headers = response.headers();
+        assert!(!headers.contains_key(CONTENT_ENCODING));
+        assert_eq!(headers[ACCEPT_RANGES], "bytes");
+        assert_eq!(headers[ETAG], "\"9c4-abc\"");
+    }
+}
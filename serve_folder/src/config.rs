@@ -0,0 +1,496 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use warp::http::header::{HeaderName, HeaderValue, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING};
+
+use crate::exclude::ExcludeSet;
+use crate::ignore_file::IgnoreFile;
+use crate::zip::CompressionLevel;
+
+pub const USAGE: &str = "\
+Usage: serve_folder <directory or file> [options]
+
+Options:
+  -p, --port <port>       Port to listen on (default 8080)
+  --auto-port             If the port is taken, use the next free one
+  --host <addr>           Address to bind, IPv4 or IPv6 (default 0.0.0.0)
+  --ipv6                  Bind :: instead of 0.0.0.0 (same as --host ::)
+  --base-path <path>      Serve everything under this URL prefix (e.g. /files)
+  --compression <level>   ZIP compression: store, fast, default or best
+  --deterministic         Build byte-identical ZIPs for unchanged folders,
+                          so interrupted downloads can resume
+  --max-file-size <size>  Hide and refuse files larger than this (e.g. 2g)
+  --mem-zip-limit <size>  Build ZIPs of folders up to this size in memory
+                          instead of a temp file (e.g. 20m)
+  --max-concurrent-zips <n>
+                          Build at most n archives at a time
+  --no-zip                Turn off ZIP and tar.gz downloads of folders and
+                          selections, for low-powered machines
+  --allow-upload          Allow uploading files through the web UI
+  --max-upload-size <size>
+                          Reject uploads larger than this (e.g. 100m)
+  --allow-delete          Allow deleting files through the web UI
+  --follow-symlinks       Serve symlinks that point outside the directory
+  --no-compress           Don't gzip/deflate text responses
+  --no-cache              Read directories afresh for every listing
+  --cors <origin>         Let pages from this origin call the API, or * for
+                          any origin (repeatable)
+  --header <header>       Add 'Name: Value' to every response, replacing
+                          any the server sets itself (repeatable)
+  --rate-limit <rate>     Cap total download speed, in bytes per second
+                          (accepts k, m and g suffixes, e.g. 500k)
+  --request-limit <n>     Archive and stop requests allowed per client per
+                          minute (default 30, 0 for no limit)
+  --show-hidden           Show and serve dotfiles such as .git and .env
+  --exclude <glob>        Hide matching files from listings and archives
+                          (repeatable; adds to .servefolderignore)
+  --index <file>          Serve this file (e.g. index.html) for directories
+  --simple                Render plain HTML listings for directories instead
+                          of redirecting to the web UI
+  --no-listing            Serve files by their URL but refuse to list
+                          folders, like Apache's Options -Indexes
+  --watch                 Refresh the web UI when files change on disk
+  --sort-files-with-dirs  Sort folders among files instead of first, unless
+                          a listing request asks otherwise
+  --title <text>          Title shown in the web UI (default: File Server)
+  --idle-timeout <mins>   Stop after this many minutes without requests
+  --open                  Open the web UI in a browser once started
+  --qr                    Print a QR code of the network URL at startup
+  --config <file>         Read options from a JSON file (default:
+                          serve-folder.json in the directory, if present)
+  --access-log <file>     Append a JSON line per request to this file
+  --log-max-size <size>   Rotate the access log when it reaches this size
+                          (e.g. 10m; keeps 5 old files)
+  --trust-proxy           Take client addresses from X-Forwarded-For
+  -q, --quiet             Print nothing but errors
+  -v, --verbose           Log every request, not just errors";
+
+// Port used when --port isn't given
+pub const DEFAULT_PORT: u16 = 8080;
+
+// Archive and stop requests a client may make per minute by default
+pub const DEFAULT_REQUEST_LIMIT: u32 = 30;
+
+// Config file picked up from the served directory when --config isn't given
+pub const CONFIG_FILE_NAME: &str = "serve-folder.json";
+
+// The only keys a picked-up serve-folder.json may set. Whoever can write to
+// the served folder controls that file, so it can change how things look or
+// narrow what's shared, but granting access takes an explicit --config
+pub const AUTO_CONFIG_KEYS: &[&str] = &[
+    "title",
+    "sort-files-with-dirs",
+    "compression",
+    "deterministic",
+    "exclude",
+    "no-listing",
+    "no-zip",
+    "no-compress",
+];
+
+// Settings collected from the command line
+#[derive(Clone, Default)]
+pub struct Config {
+    pub root_path: PathBuf,
+    pub canonical_root: PathBuf,
+    // When a file was given instead of a directory: its name, within
+    // root_path (its folder), and the only thing served
+    pub single_file: Option<PathBuf>,
+    pub port: u16,
+    // Try the following ports when `port` is already in use
+    pub auto_port: bool,
+    // Address to bind; None means every IPv4 interface
+    pub host: Option<IpAddr>,
+    // URL prefix every route lives under: empty, or e.g. "/files"
+    pub base_path: String,
+    pub compression: CompressionLevel,
+    // Same folder contents give the same ZIP bytes, at some cost to parallelism
+    pub deterministic: bool,
+    pub max_concurrent_zips: Option<usize>,
+    // Archive routes, share links included, aren't served at all
+    pub no_zip: bool,
+    // Folders with at most this many bytes are zipped without a temp file
+    pub mem_zip_limit: Option<u64>,
+    // Files over this many bytes are left out everywhere
+    pub max_file_size: Option<u64>,
+    pub allow_upload: bool,
+    pub max_upload_size: Option<u64>,
+    pub allow_delete: bool,
+    pub follow_symlinks: bool,
+    pub show_hidden: bool,
+    pub excludes: ExcludeSet,
+    // The root's .servefolderignore as it was at startup
+    pub ignore_file: IgnoreFile,
+    pub no_compress: bool,
+    pub no_cache: bool,
+    // Origins allowed to call the API cross-origin; "*" allows any
+    pub cors_origins: Vec<String>,
+    // Added to every response by --header, in order, over the server's own
+    pub extra_headers: Vec<(HeaderName, HeaderValue)>,
+    pub rate_limit: Option<u64>,
+    // Per-client requests per minute on the archive and stop endpoints;
+    // None means unlimited
+    pub request_limit: Option<u32>,
+    pub index: Option<String>,
+    pub simple: bool,
+    // Files are served but folder contents can't be listed or searched
+    pub no_listing: bool,
+    // Watch the served folder and push changes to the web UI
+    pub watch: bool,
+    // Listings mix folders in with files by default instead of putting
+    // them first
+    pub sort_files_with_dirs: bool,
+    pub title: Option<String>,
+    pub idle_timeout: Option<Duration>,
+    pub open: bool,
+    pub qr: bool,
+    pub verbose: bool,
+    // No startup or shutdown messages; errors still go to stderr
+    pub quiet: bool,
+    pub access_log: Option<PathBuf>,
+    pub log_max_size: Option<u64>,
+    // Requests arrive through a reverse proxy that sets X-Forwarded-For
+    pub trust_proxy: bool,
+    // The JSON file options were read from, if any
+    pub config_file: Option<PathBuf>,
+}
+
+impl Config {
+    // Parse the arguments that follow the program name, merged with the
+    // config file: file options come first so command-line flags override
+    // them, and repeatable ones (--exclude) add to them
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let config = Self::parse_args(args)?;
+        
+        let (config_file, allowed_keys) = match &config.config_file {
+            Some(path) => (path.clone(), None),
+            None => {
+                let path = config.root_path.join(CONFIG_FILE_NAME);
+                // A single file's folder isn't the one being shared
+                if config.single_file.is_some() || !path.is_file() {
+                    return Ok(config);
+                }
+                (path, Some(AUTO_CONFIG_KEYS))
+            }
+        };
+        
+        let mut merged = config_file_args(&config_file, allowed_keys)?;
+        merged.extend(args.iter().cloned());
+        let mut config = Self::parse_args(&merged)
+            .map_err(|e| format!("{} (with options from {})", e, config_file.display()))?;
+        config.config_file = Some(config_file);
+        Ok(config)
+    }
+    
+    // Socket address the server listens on
+    pub fn bind_address(&self) -> SocketAddr {
+        let host = self.host.unwrap_or(IpAddr::from([0, 0, 0, 0]));
+        SocketAddr::new(host, self.port)
+    }
+    
+    fn parse_args(args: &[String]) -> Result<Self, String> {
+        let mut config = Config {
+            port: DEFAULT_PORT,
+            request_limit: Some(DEFAULT_REQUEST_LIMIT),
+            ..Config::default()
+        };
+        let mut root_path = None;
+        let mut exclude_patterns = Vec::new();
+        
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-p" | "--port" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.port = value.parse::<u16>()
+                        .ok()
+                        .filter(|port| *port != 0)
+                        .ok_or_else(|| format!("Invalid port '{}'", value))?;
+                }
+                "--auto-port" => config.auto_port = true,
+                "--host" => {
+                    let value = flag_value(arg, args.next())?;
+                    // Allow the bracketed form used in URLs, e.g. [::1]
+                    let literal = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+                    config.host = Some(literal.parse::<IpAddr>()
+                        .map_err(|_| format!("Invalid host address '{}'", value))?);
+                }
+                "--ipv6" => config.host = Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+                "--base-path" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.base_path = parse_base_path(value)
+                        .ok_or_else(|| format!("Invalid base path '{}'", value))?;
+                }
+                "--compression" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.compression = CompressionLevel::parse(value)
+                        .ok_or_else(|| format!("Unknown compression level '{}'", value))?;
+                }
+                "--deterministic" => config.deterministic = true,
+                "--max-concurrent-zips" => {
+                    let value = flag_value(arg, args.next())?;
+                    let limit = value.parse::<usize>()
+                        .ok()
+                        .filter(|limit| *limit != 0)
+                        .ok_or_else(|| format!("Invalid archive limit '{}'", value))?;
+                    config.max_concurrent_zips = Some(limit);
+                }
+                "--no-zip" => config.no_zip = true,
+                "--mem-zip-limit" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.mem_zip_limit = Some(parse_size(value)
+                        .ok_or_else(|| format!("Invalid size '{}'", value))?);
+                }
+                "--max-file-size" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.max_file_size = Some(parse_size(value)
+                        .ok_or_else(|| format!("Invalid file size '{}'", value))?);
+                }
+                "--allow-upload" => config.allow_upload = true,
+                "--max-upload-size" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.max_upload_size = Some(parse_size(value)
+                        .ok_or_else(|| format!("Invalid upload size '{}'", value))?);
+                }
+                "--allow-delete" => config.allow_delete = true,
+                "--follow-symlinks" => config.follow_symlinks = true,
+                "--show-hidden" => config.show_hidden = true,
+                "--exclude" => {
+                    let value = flag_value(arg, args.next())?;
+                    exclude_patterns.push(value.to_string());
+                }
+                "--no-compress" => config.no_compress = true,
+                "--no-cache" => config.no_cache = true,
+                "--cors" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.cors_origins.push(value.to_string());
+                }
+                "--header" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.extra_headers.push(parse_header(value)?);
+                }
+                "--rate-limit" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.rate_limit = Some(parse_size(value)
+                        .ok_or_else(|| format!("Invalid rate limit '{}'", value))?);
+                }
+                "--request-limit" => {
+                    let value = flag_value(arg, args.next())?;
+                    let limit = value.parse::<u32>()
+                        .map_err(|_| format!("Invalid request limit '{}'", value))?;
+                    config.request_limit = (limit != 0).then_some(limit);
+                }
+                "--index" => {
+                    let value = flag_value(arg, args.next())?;
+                    // A bare file name; it's looked up in each directory
+                    if Path::new(value).file_name() != Some(OsStr::new(value)) {
+                        return Err(format!("Index must be a file name, not '{}'", value));
+                    }
+                    config.index = Some(value.to_string());
+                }
+                "--simple" => config.simple = true,
+                "--no-listing" => config.no_listing = true,
+                "--watch" => config.watch = true,
+                "--sort-files-with-dirs" => config.sort_files_with_dirs = true,
+                "--title" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.title = Some(value.to_string());
+                }
+                "--idle-timeout" => {
+                    let value = flag_value(arg, args.next())?;
+                    let minutes = value.parse::<u64>()
+                        .ok()
+                        .filter(|minutes| *minutes != 0)
+                        .ok_or_else(|| format!("Invalid idle timeout '{}'", value))?;
+                    config.idle_timeout = Some(Duration::from_secs(minutes * 60));
+                }
+                "--open" => config.open = true,
+                "--qr" => config.qr = true,
+                "--config" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.config_file = Some(PathBuf::from(value));
+                }
+                "-v" | "--verbose" => config.verbose = true,
+                "-q" | "--quiet" => config.quiet = true,
+                "--access-log" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.access_log = Some(PathBuf::from(value));
+                }
+                "--log-max-size" => {
+                    let value = flag_value(arg, args.next())?;
+                    config.log_max_size = Some(parse_size(value)
+                        .ok_or_else(|| format!("Invalid log size '{}'", value))?);
+                }
+                "--trust-proxy" => config.trust_proxy = true,
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option '{}'", flag));
+                }
+                path => {
+                    if root_path.is_some() {
+                        return Err("Only one directory can be served".to_string());
+                    }
+                    root_path = Some(PathBuf::from(path));
+                }
+            }
+        }
+        
+        config.excludes = ExcludeSet::new(&exclude_patterns)?;
+        if config.quiet && config.verbose {
+            return Err("--quiet and --verbose can't be used together".to_string());
+        }
+        if config.simple && config.no_listing {
+            return Err("--simple and --no-listing can't be used together".to_string());
+        }
+        
+        let mut root_path = root_path.ok_or_else(|| "No directory given".to_string())?;
+        if root_path.is_file() {
+            // Serve the file's folder with everything else in it hidden
+            let file_name = root_path.file_name()
+                .ok_or_else(|| "Provided path is not a directory".to_string())?;
+            config.single_file = Some(PathBuf::from(file_name));
+            root_path = match root_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            if config.allow_upload || config.allow_delete {
+                return Err("--allow-upload and --allow-delete can't be used when serving a single file".to_string());
+            }
+        } else if !root_path.is_dir() {
+            return Err("Provided path is not a directory or file".to_string());
+        }
+        config.canonical_root = fs::canonicalize(&root_path)
+            .map_err(|e| format!("Cannot resolve {}: {}", root_path.display(), e))?;
+        // A single file's folder isn't the one being shared
+        if config.single_file.is_none() {
+            config.ignore_file = IgnoreFile::load(&root_path)?;
+        }
+        config.root_path = root_path;
+        
+        Ok(config)
+    }
+}
+
+// A positive byte count, optionally with a k, m or g (binary) suffix
+fn parse_size(value: &str) -> Option<u64> {
+    let lower = value.to_ascii_lowercase();
+    let (digits, multiplier) = match lower.char_indices().last() {
+        Some((i, 'k')) => (&lower[..i], 1024),
+        Some((i, 'm')) => (&lower[..i], 1024 * 1024),
+        Some((i, 'g')) => (&lower[..i], 1024 * 1024 * 1024),
+        _ => (lower.as_str(), 1),
+    };
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|rate| rate.checked_mul(multiplier))
+        .filter(|rate| *rate != 0)
+}
+
+// A "Name: Value" header for --header. Headers that describe how the body
+// is framed or encoded are refused, since overriding them breaks responses
+fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, header_value) = value.split_once(':')
+        .ok_or_else(|| format!("Invalid header '{}': expected 'Name: Value'", value))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("Invalid header name '{}'", name.trim()))?;
+    if [CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING].contains(&name) {
+        return Err(format!("The {} header can't be set with --header", name));
+    }
+    let header_value = HeaderValue::from_str(header_value.trim())
+        .map_err(|_| format!("Invalid value for header {}", name))?;
+    Ok((name, header_value))
+}
+
+// Normalize a URL prefix to "/a/b" form ("" for the root). Segments must be
+// plain names, as they're matched literally and put into links
+fn parse_base_path(value: &str) -> Option<String> {
+    let mut base_path = String::new();
+    for segment in value.split('/').filter(|segment| !segment.is_empty()) {
+        let plain = segment.chars().all(|c| c.is_ascii_alphanumeric() || "-_.~".contains(c));
+        if !plain || segment == "." || segment == ".." {
+            return None;
+        }
+        base_path.push('/');
+        base_path.push_str(segment);
+    }
+    Some(base_path)
+}
+
+// Get the value following a flag, or explain that it's missing
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    value
+        .map(|v| v.as_str())
+        .ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+// Turn a JSON config file into the equivalent flags. Keys are long option
+// names without the dashes: booleans switch a flag on, strings and numbers
+// become its value, and arrays repeat it. With `allowed_keys`, any other key
+// is an error
+fn config_file_args(path: &Path, allowed_keys: Option<&[&str]>) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let settings: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+    
+    let mut args = Vec::new();
+    for (key, value) in settings {
+        if key == "config" || key.starts_with('-') {
+            return Err(format!("Unsupported setting '{}' in {}", key, path.display()));
+        }
+        if allowed_keys.is_some_and(|allowed| !allowed.contains(&key.as_str())) {
+            return Err(format!(
+                "'{}' in {} is only read from a file given with --config {}",
+                key, path.display(), path.display()
+            ));
+        }
+        let flag = format!("--{}", key);
+        let values = match value {
+            serde_json::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                serde_json::Value::Bool(true) => args.push(flag.clone()),
+                serde_json::Value::Bool(false) | serde_json::Value::Null => {}
+                serde_json::Value::String(value) => args.extend([flag.clone(), value]),
+                serde_json::Value::Number(value) => args.extend([flag.clone(), value.to_string()]),
+                _ => return Err(format!("Invalid value for '{}' in {}", key, path.display())),
+            }
+        }
+    }
+    
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn args_for(root: &Path, extra: &[&str]) -> Vec<String> {
+        std::iter::once(root.to_str().unwrap()).chain(extra.iter().copied()).map(String::from).collect()
+    }
+    
+    #[test]
+    fn served_config_file_only_sets_harmless_keys() {
+        let root = tempfile::tempdir().unwrap();
+        let config_path = root.path().join(CONFIG_FILE_NAME);
+        
+        fs::write(&config_path, r#"{"title": "Photos", "exclude": ["*.tmp"]}"#).unwrap();
+        let config = Config::from_args(&args_for(root.path(), &[])).unwrap();
+        assert_eq!(config.title.as_deref(), Some("Photos"));
+        assert_eq!(config.config_file.as_deref(), Some(config_path.as_path()));
+        
+        for setting in [r#"{"allow-delete": true}"#, r#"{"access-log": "/tmp/x.log"}"#, r#"{"host": "0.0.0.0"}"#] {
+            fs::write(&config_path, setting).unwrap();
+            assert!(Config::from_args(&args_for(root.path(), &[])).is_err(), "{}", setting);
+        }
+        
+        // Named explicitly, the same file may grant access
+        let config_arg = config_path.to_str().unwrap();
+        fs::write(&config_path, r#"{"allow-delete": true}"#).unwrap();
+        let config = Config::from_args(&args_for(root.path(), &["--config", config_arg])).unwrap();
+        assert!(config.allow_delete);
+    }
+}
@@ -0,0 +1,120 @@
+use warp::http::{HeaderValue, StatusCode};
+use warp::http::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, VARY,
+};
+use warp::reply::Response;
+
+// Methods the API answers to, offered in preflight replies
+const ALLOWED_METHODS: &str = "GET, POST, DELETE, OPTIONS";
+
+// Response headers cross-origin scripts may read besides the basic ones
+const EXPOSED_HEADERS: &str = "Content-Disposition, Content-Length, X-Operation-Id, X-Skipped-Files";
+
+// How long browsers may cache a preflight answer, in seconds
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+// Origins allowed to call the API from other sites (--cors). This is done
+// here rather than with warp::cors(), which refuses every request whose
+// Origin isn't listed, including the web UI's own same-origin POSTs
+pub struct CorsPolicy {
+    any_origin: bool,
+    origins: Vec<String>,
+}
+
+impl CorsPolicy {
+    // None when no origins were given, so CORS stays off
+    pub fn new(origins: &[String]) -> Option<Self> {
+        if origins.is_empty() {
+            return None;
+        }
+        Some(Self {
+            any_origin: origins.iter().any(|origin| origin == "*"),
+            origins: origins.iter().map(|origin| origin.trim_end_matches('/').to_string()).collect(),
+        })
+    }
+    
+    pub fn allows(&self, origin: &str) -> bool {
+        self.any_origin || self.origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    }
+    
+    // Allow-Origin value for a request from `origin`, if it's allowed
+    fn allow_origin(&self, origin: &str) -> Option<HeaderValue> {
+        if self.any_origin {
+            return Some(HeaderValue::from_static("*"));
+        }
+        if self.allows(origin) {
+            return HeaderValue::from_str(origin).ok();
+        }
+        None
+    }
+}
+
+// Add CORS headers to an API reply for an allowed cross-origin request
+pub fn cors_reply(mut response: Response, origin: Option<&str>, policy: Option<&CorsPolicy>) -> Response {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return response,
+    };
+    
+    let headers = response.headers_mut();
+    // The reply differs by Origin unless every origin gets the same one
+    if !policy.any_origin {
+        headers.append(VARY, HeaderValue::from_static("origin"));
+    }
+    if let Some(allow_origin) = origin.and_then(|origin| policy.allow_origin(origin)) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSED_HEADERS));
+    }
+    response
+}
+
+// Answer an OPTIONS preflight. Disallowed origins get no CORS headers,
+// which makes the browser block the real request
+pub fn preflight_reply(request_headers: Option<&str>) -> Response {
+    let mut response = Response::default();
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static(ALLOWED_METHODS));
+    // Any request header is fine: none of them grant extra access
+    let allow_headers = request_headers
+        .and_then(|requested| HeaderValue::from_str(requested).ok())
+        .unwrap_or_else(|| HeaderValue::from_static("content-type"));
+    headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+    headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static(PREFLIGHT_MAX_AGE));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn policy(origins: &[&str]) -> CorsPolicy {
+        CorsPolicy::new(&origins.iter().map(|origin| origin.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+    
+    #[test]
+    fn listed_origin_is_echoed() {
+        let policy = policy(&["https://app.example.com/"]);
+        let response = cors_reply(Response::default(), Some("https://app.example.com"), Some(&policy));
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+        assert_eq!(response.headers()[VARY], "origin");
+        
+        let response = cors_reply(Response::default(), Some("https://evil.example"), Some(&policy));
+        assert!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+    
+    #[test]
+    fn wildcard_allows_any_origin() {
+        let policy = policy(&["*"]);
+        let response = cors_reply(Response::default(), Some("https://anything.test"), Some(&policy));
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+    
+    #[test]
+    fn no_policy_adds_nothing() {
+        assert!(CorsPolicy::new(&[]).is_none());
+        let response = cors_reply(Response::default(), Some("https://app.example.com"), None);
+        assert!(response.headers().is_empty());
+    }
+}
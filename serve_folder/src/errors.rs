@@ -0,0 +1,94 @@
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::reject::{InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader, PayloadTooLarge, UnsupportedMediaType};
+use warp::filters::body::BodyDeserializeError;
+use warp::{Rejection, Reply};
+
+use crate::handlers::json_message;
+use crate::models::{ArchivesDisabled, FileTooLarge, ListError, RateLimited, ZipCreationError};
+use crate::web::not_found_page;
+
+// Map rejections under /api onto `{success, message}` JSON bodies
+pub async fn handle_api_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    // Listing failures carry details the UI shows next to the path
+    if let Some(list_error) = err.find::<ListError>() {
+        let reply = warp::reply::json(&serde_json::json!({
+            "success": false,
+            "message": format!("Cannot read directory: {}", list_error.message),
+            "current_path": list_error.current_path,
+            "kind": list_error.kind,
+            "errno": list_error.errno
+        }));
+        return Ok(warp::reply::with_status(reply, list_error.status()).into_response());
+    }
+    
+    if let Some(zip_error) = err.find::<ZipCreationError>() {
+        let reply = warp::reply::json(&serde_json::json!({
+            "success": false,
+            "message": format!("Failed to create archive: {}", zip_error.reason),
+            "kind": zip_error.kind
+        }));
+        return Ok(warp::reply::with_status(reply, zip_error.status()).into_response());
+    }
+    
+    if let Some(rate_limited) = err.find::<RateLimited>() {
+        let mut response = json_message(StatusCode::TOO_MANY_REQUESTS, false, "Too many requests; try again shortly");
+        response.headers_mut().insert(warp::http::header::RETRY_AFTER, rate_limited.retry_after_secs.into());
+        return Ok(response);
+    }
+    
+    // Say what was wrong with a JSON body, not just that it was wrong
+    if let Some(body_error) = err.find::<BodyDeserializeError>() {
+        let detail = std::error::Error::source(body_error)
+            .map(|source| source.to_string())
+            .unwrap_or_else(|| body_error.to_string());
+        return Ok(json_message(StatusCode::BAD_REQUEST, false, &format!("Invalid request body: {}", detail)));
+    }
+    
+    let (status, message) = rejection_status(&err);
+    Ok(json_message(status, false, message))
+}
+
+// Render a friendly HTML page for missing files, plain text otherwise
+pub async fn handle_rejection(err: Rejection, base_path: String) -> Result<impl Reply, Infallible> {
+    let (status, message) = rejection_status(&err);
+    if status == StatusCode::NOT_FOUND {
+        let page = warp::reply::html(not_found_page(&base_path));
+        return Ok(warp::reply::with_status(page, status).into_response());
+    }
+    
+    Ok(warp::reply::with_status(message, status).into_response())
+}
+
+// Pick a status code and short message for a rejection
+fn rejection_status(err: &Rejection) -> (StatusCode, &'static str) {
+    if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found")
+    } else if let Some(list_error) = err.find::<ListError>() {
+        (list_error.status(), "Cannot read directory")
+    } else if err.find::<ArchivesDisabled>().is_some() {
+        (StatusCode::NOT_FOUND, "Archives are turned off on this server")
+    } else if err.find::<FileTooLarge>().is_some() {
+        (StatusCode::FORBIDDEN, "File is larger than this server shares")
+    } else if err.find::<RateLimited>().is_some() {
+        (StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+    } else if let Some(zip_error) = err.find::<ZipCreationError>() {
+        (zip_error.status(), "Failed to create archive")
+    } else if err.find::<BodyDeserializeError>().is_some() {
+        (StatusCode::BAD_REQUEST, "Invalid request body")
+    } else if err.find::<InvalidQuery>().is_some() {
+        (StatusCode::BAD_REQUEST, "Invalid query parameters")
+    } else if err.find::<MissingHeader>().is_some() || err.find::<InvalidHeader>().is_some() {
+        (StatusCode::BAD_REQUEST, "Invalid request headers")
+    } else if err.find::<LengthRequired>().is_some() {
+        (StatusCode::LENGTH_REQUIRED, "Content-Length required")
+    } else if err.find::<PayloadTooLarge>().is_some() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large")
+    } else if err.find::<UnsupportedMediaType>().is_some() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported media type")
+    } else if err.find::<MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
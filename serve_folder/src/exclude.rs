@@ -0,0 +1,99 @@
+use std::path::Path;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+// Paths hidden by --exclude. Patterns are matched against the path
+// relative to the served root (with `/` separators) and against the
+// entry's own name, so `node_modules` hides it at any depth while
+// `docs/*.tmp` only applies under docs. Any matching pattern excludes
+// the entry; there is no re-including, and excluding a directory
+// hides everything beneath it.
+#[derive(Clone, Default)]
+pub struct ExcludeSet {
+    globs: GlobSet,
+}
+
+impl ExcludeSet {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern)
+                .map_err(|e| format!("Invalid exclude pattern '{}': {}", pattern, e))?;
+            builder.add(glob);
+        }
+        let globs = builder.build()
+            .map_err(|e| format!("Invalid exclude patterns: {}", e))?;
+        Ok(Self { globs })
+    }
+    
+    // Whether a root-relative path is excluded, by itself or via an
+    // excluded ancestor directory
+    pub fn is_excluded(&self, rel_path: &Path) -> bool {
+        if self.globs.is_empty() {
+            return false;
+        }
+        
+        let mut prefix = String::new();
+        for component in rel_path.components() {
+            let name = component.as_os_str().to_string_lossy();
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(&name);
+            if self.globs.is_match(name.as_ref()) || self.globs.is_match(&prefix) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn set(patterns: &[&str]) -> ExcludeSet {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        ExcludeSet::new(&patterns).unwrap()
+    }
+    
+    #[test]
+    fn empty_set_excludes_nothing() {
+        assert!(!ExcludeSet::default().is_excluded(Path::new("a/b.txt")));
+    }
+    
+    #[test]
+    fn bare_name_matches_at_any_depth() {
+        let excludes = set(&["node_modules"]);
+        assert!(excludes.is_excluded(Path::new("node_modules")));
+        assert!(excludes.is_excluded(Path::new("web/node_modules")));
+        assert!(!excludes.is_excluded(Path::new("web/node_modules_old")));
+    }
+    
+    #[test]
+    fn children_of_excluded_directories_are_excluded() {
+        let excludes = set(&[".git"]);
+        assert!(excludes.is_excluded(Path::new(".git/objects/ab/cdef")));
+        assert!(excludes.is_excluded(Path::new("vendor/lib/.git/HEAD")));
+        assert!(!excludes.is_excluded(Path::new(".gitignore")));
+    }
+    
+    #[test]
+    fn wildcard_matches_nested_files() {
+        let excludes = set(&["*.tmp"]);
+        assert!(excludes.is_excluded(Path::new("scratch.tmp")));
+        assert!(excludes.is_excluded(Path::new("a/b/c/scratch.tmp")));
+        assert!(!excludes.is_excluded(Path::new("a/b/c/scratch.txt")));
+    }
+    
+    #[test]
+    fn path_patterns_are_anchored_at_the_root() {
+        let excludes = set(&["docs/drafts"]);
+        assert!(excludes.is_excluded(Path::new("docs/drafts/intro.md")));
+        assert!(!excludes.is_excluded(Path::new("old/docs/drafts/intro.md")));
+    }
+    
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        assert!(ExcludeSet::new(&["[".to_string()]).is_err());
+    }
+}
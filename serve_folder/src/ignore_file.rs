@@ -0,0 +1,84 @@
+use std::path::Path;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+// Read from the served root, like a .gitignore
+pub const IGNORE_FILE_NAME: &str = ".servefolderignore";
+
+// Paths hidden by the root's .servefolderignore. Lines use .gitignore
+// syntax: patterns are relative to the root, a trailing `/` matches only
+// directories, and `!` re-includes a path an earlier line ignored. It
+// adds to --exclude and to hiding dotfiles: `!` can't bring back a path
+// hidden by either, and excluding a directory hides everything beneath it.
+#[derive(Clone, Default)]
+pub struct IgnoreFile {
+    // None when the root has no ignore file
+    matcher: Option<Gitignore>,
+}
+
+impl IgnoreFile {
+    // Parse root/.servefolderignore. A missing file ignores nothing; an
+    // unreadable one or a bad pattern is an error
+    pub fn load(root: &Path) -> Result<Self, String> {
+        let path = root.join(IGNORE_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        
+        let mut builder = GitignoreBuilder::new(root);
+        // The error already names the file and line
+        if let Some(e) = builder.add(&path) {
+            return Err(format!("Invalid ignore file {}", e));
+        }
+        let matcher = builder.build()
+            .map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        Ok(Self { matcher: Some(matcher) })
+    }
+    
+    // Whether a root-relative path is ignored, by itself or via an ignored
+    // ancestor directory. `is_dir` is only asked when there are patterns
+    pub fn is_ignored(&self, rel_path: &Path, is_dir: impl FnOnce() -> bool) -> bool {
+        match &self.matcher {
+            Some(matcher) if !rel_path.as_os_str().is_empty() => {
+                matcher.matched_path_or_any_parents(rel_path, is_dir()).is_ignore()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    
+    fn load(lines: &str) -> IgnoreFile {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(IGNORE_FILE_NAME), lines).unwrap();
+        IgnoreFile::load(root.path()).unwrap()
+    }
+    
+    #[test]
+    fn missing_file_ignores_nothing() {
+        let root = tempfile::tempdir().unwrap();
+        let ignore_file = IgnoreFile::load(root.path()).unwrap();
+        assert!(!ignore_file.is_ignored(Path::new("a.txt"), || false));
+    }
+    
+    #[test]
+    fn patterns_follow_gitignore_rules() {
+        let ignore_file = load("# build output\n*.log\n!keep.log\n/private\nbuild/\n");
+        assert!(ignore_file.is_ignored(Path::new("logs/app.log"), || false));
+        assert!(!ignore_file.is_ignored(Path::new("logs/keep.log"), || false));
+        assert!(ignore_file.is_ignored(Path::new("private/notes.txt"), || false));
+        assert!(!ignore_file.is_ignored(Path::new("docs/private"), || false));
+        assert!(ignore_file.is_ignored(Path::new("web/build"), || true));
+        assert!(!ignore_file.is_ignored(Path::new("web/build"), || false));
+    }
+    
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(IGNORE_FILE_NAME), "[z-a]\n").unwrap();
+        assert!(IgnoreFile::load(root.path()).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Clients tracked before idle ones are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// Token buckets per client address for the expensive endpoints (archives
+// and /api/stop). Each client may make `per_minute` requests in a burst,
+// then one more every 60/per_minute seconds
+pub struct IpRateLimiter {
+    per_minute: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl IpRateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute: f64::from(per_minute),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    // Take one request from `ip`'s bucket. Err holds how long until the
+    // next one is allowed
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+    
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let per_sec = self.per_minute / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&ip) {
+            // A full bucket is the same as no bucket, so those can go
+            let per_minute = self.per_minute;
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * per_sec < per_minute
+            });
+        }
+        
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.per_minute,
+            refilled_at: now,
+        });
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * per_sec;
+        bucket.tokens = (bucket.tokens + refill).min(self.per_minute);
+        bucket.refilled_at = now;
+        
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn burst_is_throttled_per_client() {
+        let limiter = IpRateLimiter::new(3);
+        let client = IpAddr::from([192, 0, 2, 1]);
+        let other = IpAddr::from([192, 0, 2, 2]);
+        let now = Instant::now();
+        
+        for _ in 0..3 {
+            assert!(limiter.check_at(client, now).is_ok());
+        }
+        let retry_after = limiter.check_at(client, now).unwrap_err();
+        assert_eq!(retry_after.as_secs(), 20);
+        // Other clients have their own allowance
+        assert!(limiter.check_at(other, now).is_ok());
+        
+        // One request comes back every 20 seconds
+        assert!(limiter.check_at(client, now + Duration::from_secs(20)).is_ok());
+        assert!(limiter.check_at(client, now + Duration::from_secs(20)).is_err());
+    }
+}
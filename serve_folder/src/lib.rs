@@ -0,0 +1,38 @@
+// The server as a library: build_routes for embedding its routes in
+// another warp app, run_server for running it the way the binary does
+pub mod access_log;
+pub mod autoindex;
+pub mod config;
+pub mod models;
+pub mod paths;
+pub mod state;
+pub mod handlers;
+pub mod listing_cache;
+pub mod zip;
+pub mod targz;
+pub mod range;
+pub mod web;
+pub mod mime;
+pub mod errors;
+pub mod logging;
+pub mod compression;
+pub mod cors;
+pub mod banner;
+pub mod checksum;
+pub mod client_ip;
+pub mod exclude;
+pub mod ignore_file;
+pub mod ip_limit;
+pub mod caching;
+pub mod thumbnail;
+pub mod throttle;
+pub mod ws;
+pub mod openapi;
+pub mod routes;
+pub mod server;
+pub mod share;
+pub mod watch;
+
+pub use config::{Config as ServerConfig, USAGE};
+pub use routes::build_routes;
+pub use server::run_server;
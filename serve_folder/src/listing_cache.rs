@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::models::FileEntry;
+
+// Most directories kept, and most entries across all of them, so one huge
+// folder can't hold on to unbounded memory
+const MAX_DIRECTORIES: usize = 256;
+const MAX_ENTRIES: usize = 200_000;
+
+struct CachedListing {
+    mtime: SystemTime,
+    entries: Vec<FileEntry>,
+    last_used: u64,
+}
+
+// Directory listings keyed by path, reused while the directory's mtime is
+// unchanged. Adding, removing or renaming an entry bumps that mtime; a file
+// changing in place doesn't, so writers through this server also invalidate
+#[derive(Default)]
+pub struct ListingCache {
+    listings: HashMap<PathBuf, CachedListing>,
+    total_entries: usize,
+    // Logical clock for least-recently-used eviction
+    clock: u64,
+}
+
+impl ListingCache {
+    pub fn new() -> Self {
+        Self {
+            listings: HashMap::new(),
+            total_entries: 0,
+            clock: 0,
+        }
+    }
+    
+    // The cached entries for `dir`, if it hasn't changed since they were read
+    pub fn get(&mut self, dir: &Path, mtime: SystemTime) -> Option<Vec<FileEntry>> {
+        self.clock += 1;
+        let listing = self.listings.get_mut(dir)?;
+        if listing.mtime != mtime {
+            self.invalidate(dir);
+            return None;
+        }
+        listing.last_used = self.clock;
+        Some(listing.entries.clone())
+    }
+    
+    pub fn insert(&mut self, dir: &Path, mtime: SystemTime, entries: &[FileEntry]) {
+        if entries.len() > MAX_ENTRIES {
+            return;
+        }
+        self.invalidate(dir);
+        self.clock += 1;
+        self.total_entries += entries.len();
+        self.listings.insert(dir.to_path_buf(), CachedListing {
+            mtime,
+            entries: entries.to_vec(),
+            last_used: self.clock,
+        });
+        
+        while self.listings.len() > MAX_DIRECTORIES || self.total_entries > MAX_ENTRIES {
+            let oldest = self.listings.iter()
+                .min_by_key(|(_, listing)| listing.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => self.invalidate(&path),
+                None => break,
+            }
+        }
+    }
+    
+    pub fn clear(&mut self) {
+        self.listings.clear();
+        self.total_entries = 0;
+    }
+    
+    pub fn invalidate(&mut self, dir: &Path) {
+        if let Some(listing) = self.listings.remove(dir) {
+            self.total_entries -= listing.entries.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    
+    fn entry(name: &str) -> FileEntry {
+        FileEntry {
+            name: name.to_string(),
+            path: name.to_string(),
+            is_dir: false,
+            size: 1,
+            modified: None,
+        }
+    }
+    
+    #[test]
+    fn changed_mtime_misses() {
+        let mut cache = ListingCache::new();
+        let mtime = UNIX_EPOCH + Duration::from_secs(100);
+        cache.insert(Path::new("/a"), mtime, &[entry("x")]);
+        
+        assert_eq!(cache.get(Path::new("/a"), mtime).map(|entries| entries.len()), Some(1));
+        assert!(cache.get(Path::new("/a"), mtime + Duration::from_secs(1)).is_none());
+        // The stale listing is dropped, not kept around
+        assert!(cache.get(Path::new("/a"), mtime).is_none());
+    }
+    
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let mut cache = ListingCache::new();
+        let mtime = UNIX_EPOCH;
+        for i in 0..MAX_DIRECTORIES {
+            cache.insert(&PathBuf::from(format!("/{}", i)), mtime, &[entry("x")]);
+        }
+        // Touch the oldest so the second-oldest goes instead
+        assert!(cache.get(Path::new("/0"), mtime).is_some());
+        cache.insert(Path::new("/new"), mtime, &[entry("x")]);
+        
+        assert!(cache.get(Path::new("/0"), mtime).is_some());
+        assert!(cache.get(Path::new("/1"), mtime).is_none());
+        assert_eq!(cache.listings.len(), MAX_DIRECTORIES);
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::SystemTime;
+use warp::log::Info;
+
+use crate::client_ip::client_ip;
+
+// Log one request. Quiet mode only reports server errors; verbose mode logs
+// everything except successful progress polls, which fire several times a
+// second while a ZIP is being built.
+pub fn log_request(info: Info, verbose: bool, trust_proxy: bool) {
+    let status = info.status();
+    
+    if !verbose && !status.is_server_error() {
+        return;
+    }
+    if status.is_success() && is_progress_poll(info.path()) {
+        return;
+    }
+    
+    let forwarded_for = info.request_headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    // Connections without a peer address are shown as "-"
+    let client = client_ip(info.remote_addr(), forwarded_for, trust_proxy)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    
+    let line = format!(
+        "[{}] {} {} {} {} {}ms",
+        httpdate::fmt_http_date(SystemTime::now()),
+        client,
+        info.method(),
+        info.path(),
+        status.as_u16(),
+        info.elapsed().as_millis(),
+    );
+    
+    if status.is_server_error() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+fn is_progress_poll(path: &str) -> bool {
+    path == "/api/zip/progress"
+}
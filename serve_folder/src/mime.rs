@@ -0,0 +1,100 @@
+use std::path::Path;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+// Characters left bare in an RFC 5987 `filename*` value
+const ATTR_CHARS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'.').remove(b'-').remove(b'_').remove(b'~');
+
+// Content type for a file, falling back to a generic binary type
+pub fn content_type_for(path: &Path) -> &'static str {
+    known_content_type(path).unwrap_or("application/octet-stream")
+}
+
+// Content type for extensions we know, or None so callers can keep
+// whatever another layer guessed
+pub fn known_content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    
+    let content_type = match extension.as_str() {
+        // Text and web
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        
+        // Images
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        
+        // Audio and video
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        
+        // Documents and archives
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        
+        // Fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        
+        _ => return None,
+    };
+    
+    Some(content_type)
+}
+
+// Content-Disposition for a served file. `?download=1` always forces a save
+// dialog; otherwise things a browser can preview (text, images, PDFs,
+// audio and video) stay inline and everything else is saved under its name
+pub fn content_disposition(path: &Path, force_download: bool) -> String {
+    let previewable = known_content_type(path).is_some_and(|content_type| {
+        content_type.starts_with("text/")
+            || content_type.starts_with("image/")
+            || content_type.starts_with("audio/")
+            || content_type.starts_with("video/")
+            || content_type == "application/pdf"
+            || content_type == "application/json"
+    });
+    if previewable && !force_download {
+        return "inline".to_string();
+    }
+    
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    attachment(&name)
+}
+
+// `attachment` with an ASCII fallback name plus the exact UTF-8 name
+// (RFC 6266), so quotes and non-ASCII characters survive
+pub fn attachment(filename: &str) -> String {
+    let fallback: String = filename.chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(filename, ATTR_CHARS)
+    )
+}
@@ -1,17 +1,49 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct FileEntry {
     pub name: String,
     pub path: String,
     pub is_dir: bool,
     pub size: u64,
+    pub modified: Option<u64>,
 }
 
 #[derive(Serialize)]
 pub struct DirResponse {
     pub current_path: String,
     pub entries: Vec<FileEntry>,
+    pub total: usize,
+    pub has_more: bool,
+    // Recursive size of the directory; only set when requested and the
+    // walk finished in time
+    pub total_size: Option<u64>,
+}
+
+// A node of /api/tree. Directories above the requested depth carry their
+// children; deeper ones and files leave `children` out
+#[derive(Serialize)]
+pub struct TreeNode {
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<TreeNode>>,
+}
+
+#[derive(Serialize)]
+pub struct TreeResponse {
+    pub current_path: String,
+    pub depth: usize,
+    pub entries: Vec<TreeNode>,
+    // Set when the node cap was hit, so some directories are incomplete
+    pub truncated: bool,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub entries: Vec<FileEntry>,
+    pub truncated: bool,
 }
 
 #[derive(Deserialize)]
@@ -24,13 +56,232 @@ pub struct ZipProgress {
     pub current_file: String,
     pub processed_files: usize,
     pub total_files: usize,
+    pub processed_bytes: u64,
+    pub total_bytes: u64,
     pub percentage: f32,
+    // Recent throughput and the time left at that rate, once known
+    pub bytes_per_sec: u64,
+    pub eta_secs: Option<u64>,
+    // Unix epoch millis of the last update, used to evict abandoned entries
+    pub updated_at: u64,
+    // Files left out because they vanished or couldn't be read mid-archive
+    pub skipped: Vec<String>,
 }
 
 #[derive(Deserialize)]
 pub struct DownloadQuery {
     pub path: String,
     pub operation_id: Option<String>,
+    pub format: Option<String>,
+}
+
+// Body of POST /api/share: the folder to share, and for how many seconds
+#[derive(Deserialize)]
+pub struct ShareRequest {
+    pub path: String,
+    pub expires_in: Option<u64>,
+}
+
+// Body of POST /api/zip: files to archive, relative to the served root
+#[derive(Deserialize)]
+pub struct ZipSelectionRequest {
+    pub paths: Vec<String>,
+    pub operation_id: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    // Unknown formats fall back to ZIP
+    pub fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("targz") | Some("tar.gz") => ArchiveFormat::TarGz,
+            _ => ArchiveFormat::Zip,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+}
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    pub path: String,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub group_dirs: Option<bool>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub compute_dir_size: Option<bool>,
+    pub format: Option<String>,
+}
+
+// /api/list/stream takes no sorting or paging: entries come in the order
+// the directory yields them
+#[derive(Deserialize)]
+pub struct ListStreamQuery {
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+pub struct TreeQuery {
+    pub path: String,
+    pub depth: Option<usize>,
+}
+
+// Sort options for --simple directory pages
+#[derive(Deserialize)]
+pub struct DirectoryQuery {
+    pub sort: Option<String>,
+    pub order: Option<String>,
+}
+
+// Query string on directly served files
+#[derive(Deserialize)]
+pub struct FileQuery {
+    pub download: Option<String>,
+}
+
+impl FileQuery {
+    // `download=1` (or `true`) asks for a save dialog instead of a preview
+    pub fn forces_download(&self) -> bool {
+        matches!(self.download.as_deref(), Some("1") | Some("true"))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    // Unknown keys fall back to name sort rather than erroring
+    pub fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("size") => SortKey::Size,
+            Some("modified") => SortKey::Modified,
+            _ => SortKey::Name,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    pub path: String,
+}
+
+// Body of POST /api/mkdir: a new folder `name` inside `path`
+#[derive(Deserialize)]
+pub struct MkdirRequest {
+    pub path: String,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+pub struct CancelQuery {
+    pub operation_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct StatQuery {
+    pub path: String,
+    pub sha256: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct StatResponse {
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    pub child_count: Option<usize>,
+    pub sha256: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ThumbQuery {
+    pub path: String,
+    pub size: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct ChecksumQuery {
+    pub path: String,
+    pub checksum: Option<String>,
+    pub operation_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ChecksumResponse {
+    pub path: String,
+    pub algorithm: String,
+    pub checksum: String,
+}
+
+#[derive(Deserialize)]
+pub struct ManifestQuery {
+    pub path: String,
+}
+
+#[derive(Serialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct ManifestResponse {
+    pub path: String,
+    pub files: Vec<ManifestEntry>,
+    pub total_files: usize,
+    pub total_size: u64,
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ZipPreviewQuery {
+    pub path: String,
+}
+
+// What a ZIP of a folder would contain. The counts and size cover every
+// file; `files` stops after a limit, with `truncated` set
+#[derive(Serialize)]
+pub struct ZipPreviewResponse {
+    pub path: String,
+    pub files: Vec<ManifestEntry>,
+    pub total_files: usize,
+    pub total_size: u64,
+    // Folders, including empty ones, which get their own entries
+    pub total_directories: usize,
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub path: String,
+    pub q: String,
+}
+
+// Commands accepted on /api/ws
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WsCommand {
+    Subscribe { id: String },
+    Cancel { id: String },
 }
 
 #[derive(Deserialize)]
@@ -39,6 +290,76 @@ pub struct ProgressQuery {
 }
 
 // Error types
+
+// Building or sending an archive failed. The reason is passed on to the
+// client, so it can tell a full disk from a vanished folder
 #[derive(Debug)]
-pub struct ZipCreationError;
+pub struct ZipCreationError {
+    pub kind: String,
+    pub reason: String,
+}
 impl warp::reject::Reject for ZipCreationError {}
+
+impl ZipCreationError {
+    pub fn new(err: &std::io::Error) -> Self {
+        Self {
+            kind: format!("{:?}", err.kind()),
+            reason: err.to_string(),
+        }
+    }
+    
+    pub fn status(&self) -> warp::http::StatusCode {
+        match self.kind.as_str() {
+            "StorageFull" => warp::http::StatusCode::INSUFFICIENT_STORAGE,
+            "NotFound" => warp::http::StatusCode::NOT_FOUND,
+            _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// A client went over --request-limit; it may try again after this many
+// seconds
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+impl warp::reject::Reject for RateLimited {}
+
+// An archive route was requested under --no-zip
+#[derive(Debug)]
+pub struct ArchivesDisabled;
+impl warp::reject::Reject for ArchivesDisabled {}
+
+// A file was asked for by name but is over --max-file-size
+#[derive(Debug)]
+pub struct FileTooLarge;
+impl warp::reject::Reject for FileTooLarge {}
+
+// A directory that exists under the root but couldn't be read
+#[derive(Debug)]
+pub struct ListError {
+    pub current_path: String,
+    pub kind: String,
+    pub errno: Option<i32>,
+    pub message: String,
+}
+impl warp::reject::Reject for ListError {}
+
+impl ListError {
+    pub fn new(current_path: String, err: &std::io::Error) -> Self {
+        Self {
+            current_path,
+            kind: format!("{:?}", err.kind()),
+            errno: err.raw_os_error(),
+            message: err.to_string(),
+        }
+    }
+    
+    pub fn status(&self) -> warp::http::StatusCode {
+        match self.kind.as_str() {
+            "PermissionDenied" => warp::http::StatusCode::FORBIDDEN,
+            "NotFound" => warp::http::StatusCode::NOT_FOUND,
+            _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use warp::{Reply, Rejection};
+
+use crate::state::ServerState;
+
+// Hand-written OpenAPI 3 description of the API. The tests below check its
+// schemas against what the models actually serialize
+const SPEC: &str = include_str!("openapi.json");
+
+// Serve the spec with this build's version, and paths under --base-path
+pub async fn handle_openapi(state: ServerState) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&spec(&state.config().base_path)))
+}
+
+fn spec(base_path: &str) -> serde_json::Value {
+    let mut spec: serde_json::Value = serde_json::from_str(SPEC).expect("openapi.json is valid JSON");
+    spec["info"]["version"] = env!("CARGO_PKG_VERSION").into();
+    let server_url = if base_path.is_empty() { "/" } else { base_path };
+    spec["servers"][0]["url"] = server_url.into();
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use serde::Serialize;
+    use crate::models::{DirResponse, FileEntry, ManifestEntry, TreeNode, TreeResponse, ZipPreviewResponse, ZipProgress};
+    
+    fn entry() -> FileEntry {
+        FileEntry {
+            name: "a.txt".to_string(),
+            path: "a.txt".to_string(),
+            is_dir: false,
+            size: 1,
+            modified: Some(0),
+        }
+    }
+    
+    // Fields the model serializes must be exactly those the spec lists
+    fn assert_matches_schema(name: &str, value: impl Serialize) {
+        let spec = spec("");
+        let schema = &spec["components"]["schemas"][name];
+        let documented: BTreeSet<&String> = schema["properties"].as_object()
+            .unwrap_or_else(|| panic!("no schema for {}", name))
+            .keys()
+            .collect();
+        let value = serde_json::to_value(value).unwrap();
+        let serialized: BTreeSet<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(documented, serialized, "{} schema is out of date", name);
+    }
+    
+    #[test]
+    fn schemas_match_the_models() {
+        assert_matches_schema("FileEntry", entry());
+        assert_matches_schema("DirResponse", DirResponse {
+            current_path: String::new(),
+            entries: vec![entry()],
+            total: 1,
+            has_more: false,
+            total_size: None,
+        });
+        assert_matches_schema("TreeNode", TreeNode { entry: entry(), children: Some(Vec::new()) });
+        assert_matches_schema("TreeResponse", TreeResponse {
+            current_path: String::new(),
+            depth: 1,
+            entries: Vec::new(),
+            truncated: false,
+        });
+        assert_matches_schema("ZipProgress", ZipProgress::default());
+        assert_matches_schema("ManifestEntry", ManifestEntry { path: String::new(), size: 0 });
+        assert_matches_schema("ZipPreviewResponse", ZipPreviewResponse {
+            path: String::new(),
+            files: Vec::new(),
+            total_files: 0,
+            total_size: 0,
+            total_directories: 0,
+            truncated: false,
+        });
+    }
+    
+    #[test]
+    fn references_resolve() {
+        let spec = spec("");
+        let text = spec.to_string();
+        for reference in text.split("\"$ref\":\"#/").skip(1) {
+            let pointer = &reference[..reference.find('"').unwrap()];
+            assert!(spec.pointer(&format!("/{}", pointer)).is_some(), "dangling $ref {}", pointer);
+        }
+    }
+    
+    #[test]
+    fn base_path_and_version_are_filled_in() {
+        let spec = spec("/files");
+        assert_eq!(spec["servers"][0]["url"], "/files");
+        assert_eq!(spec["info"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+}
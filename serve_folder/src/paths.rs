@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use percent_encoding::{percent_decode_str, AsciiSet, NON_ALPHANUMERIC};
+
+// Marks a relative path given as percent-encoded bytes, for names that
+// aren't valid UTF-8 and so can't travel as JSON strings unchanged
+pub const RAW_PATH_PREFIX: &str = "~raw:";
+
+// Bytes left bare in a raw path; the rest is percent-encoded, which also
+// makes the remainder usable as a URL path
+const RAW_PATH_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC.remove(b'/').remove(b'.').remove(b'-').remove(b'_').remove(b'~');
+
+// Resolve a client-supplied relative path against the served root.
+// Returns None for anything that tries to leave the root: parent (`..`)
+// components, absolute paths, or drive/UNC prefixes. An empty path maps
+// to the root itself.
+pub fn resolve_safe_path(root: &Path, rel: &str) -> Option<PathBuf> {
+    let mut full_path = root.to_path_buf();
+    
+    for component in decode_rel_path(rel).components() {
+        match component {
+            Component::Normal(name) => full_path.push(name),
+            Component::CurDir => continue,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    
+    // Safety check
+    if !full_path.starts_with(root) {
+        return None;
+    }
+    
+    Some(full_path)
+}
+
+// A root-relative path as clients see it: `/`-separated text, or for a
+// path that isn't valid UTF-8 (or could be mistaken for an encoded one),
+// RAW_PATH_PREFIX followed by its percent-encoded bytes. Either form comes
+// back to the same OS path through decode_rel_path.
+pub fn encode_rel_path(rel: &Path) -> String {
+    match rel.to_str() {
+        Some(text) if !text.starts_with(RAW_PATH_PREFIX) => text.to_string(),
+        _ => raw_rel_path(rel),
+    }
+}
+
+#[cfg(unix)]
+fn raw_rel_path(rel: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let encoded = percent_encoding::percent_encode(rel.as_os_str().as_bytes(), RAW_PATH_ESCAPES);
+    format!("{}{}", RAW_PATH_PREFIX, encoded)
+}
+
+// Windows names are UTF-16 and convert to UTF-8 apart from lone surrogates,
+// which are rare enough to keep lossy
+#[cfg(not(unix))]
+fn raw_rel_path(rel: &Path) -> String {
+    let text = rel.to_string_lossy();
+    if text.starts_with(RAW_PATH_PREFIX) {
+        let encoded = percent_encoding::utf8_percent_encode(&text, RAW_PATH_ESCAPES);
+        return format!("{}{}", RAW_PATH_PREFIX, encoded);
+    }
+    text.to_string()
+}
+
+// Reverse of encode_rel_path
+pub fn decode_rel_path(rel: &str) -> PathBuf {
+    let encoded = match rel.strip_prefix(RAW_PATH_PREFIX) {
+        Some(encoded) => encoded,
+        None => return PathBuf::from(rel),
+    };
+    let bytes: Vec<u8> = percent_decode_str(encoded).collect();
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        PathBuf::from(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Check that a path already resolved under the root doesn't reach outside
+// the canonical root through a symlink. Paths that don't exist are judged by
+// their parent directory, so a dangling link itself can still be handled.
+pub fn is_within_root(canonical_root: &Path, path: &Path) -> bool {
+    match fs::canonicalize(path) {
+        Ok(canonical) => canonical.starts_with(canonical_root),
+        Err(_) => path.parent()
+            .and_then(|parent| fs::canonicalize(parent).ok())
+            .map(|parent| parent.starts_with(canonical_root))
+            .unwrap_or(false),
+    }
+}
+
+// Whether any component of a root-relative path is a dotfile or
+// dot-directory (`.git`, `.env`, `.DS_Store`, ...)
+pub fn is_hidden(rel_path: &Path) -> bool {
+    rel_path.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root() -> PathBuf {
+        PathBuf::from("/srv/share")
+    }
+
+    #[test]
+    fn empty_path_is_root() {
+        assert_eq!(resolve_safe_path(&root(), ""), Some(root()));
+        assert_eq!(resolve_safe_path(&root(), "."), Some(root()));
+    }
+
+    #[test]
+    fn normal_components_are_joined() {
+        assert_eq!(
+            resolve_safe_path(&root(), "docs/report.pdf"),
+            Some(root().join("docs").join("report.pdf"))
+        );
+        assert_eq!(
+            resolve_safe_path(&root(), "./docs//nested/"),
+            Some(root().join("docs").join("nested"))
+        );
+    }
+
+    #[test]
+    fn parent_components_are_rejected() {
+        assert_eq!(resolve_safe_path(&root(), ".."), None);
+        assert_eq!(resolve_safe_path(&root(), "../etc/passwd"), None);
+        assert_eq!(resolve_safe_path(&root(), "docs/../../etc"), None);
+        // Even a `..` that would stay inside the root is refused
+        assert_eq!(resolve_safe_path(&root(), "docs/../other"), None);
+    }
+
+    #[test]
+    fn absolute_paths_are_rejected() {
+        assert_eq!(resolve_safe_path(&root(), "/etc/passwd"), None);
+        assert_eq!(resolve_safe_path(&root(), "/"), None);
+    }
+
+    #[test]
+    fn symlink_style_inputs_are_rejected() {
+        // Paths that climb out through what might be a symlinked directory
+        assert_eq!(resolve_safe_path(&root(), "link/../../../etc/shadow"), None);
+        assert_eq!(resolve_safe_path(&root(), "./../share/secret"), None);
+    }
+
+    #[test]
+    fn dot_components_are_hidden() {
+        assert!(is_hidden(Path::new(".env")));
+        assert!(is_hidden(Path::new(".git/config")));
+        assert!(is_hidden(Path::new("photos/.DS_Store")));
+        assert!(!is_hidden(Path::new("photos/holiday.jpg")));
+        assert!(!is_hidden(Path::new("")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_round_trip() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        
+        let raw = Path::new(OsStr::from_bytes(b"photos/caf\xe9 menu.txt"));
+        let encoded = encode_rel_path(raw);
+        assert_eq!(encoded, "~raw:photos/caf%E9%20menu.txt");
+        assert_eq!(decode_rel_path(&encoded), raw);
+        assert_eq!(resolve_safe_path(&root(), &encoded), Some(root().join(raw)));
+        
+        // A real name that looks encoded is encoded too, so it survives
+        let lookalike = Path::new("~raw:notes.txt");
+        assert_eq!(decode_rel_path(&encode_rel_path(lookalike)), lookalike);
+        
+        assert_eq!(encode_rel_path(Path::new("docs/report.pdf")), "docs/report.pdf");
+        assert_eq!(resolve_safe_path(&root(), "~raw:..%2Fetc"), None);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn escaping_symlink_is_outside_root() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        
+        let served = tempfile::tempdir().unwrap();
+        fs::write(served.path().join("visible.txt"), "visible").unwrap();
+        std::os::unix::fs::symlink(outside.path(), served.path().join("escape")).unwrap();
+        std::os::unix::fs::symlink("visible.txt", served.path().join("inner_link")).unwrap();
+        
+        let canonical_root = fs::canonicalize(served.path()).unwrap();
+        let escape = resolve_safe_path(served.path(), "escape/secret.txt").unwrap();
+        let inner = resolve_safe_path(served.path(), "inner_link").unwrap();
+        let visible = resolve_safe_path(served.path(), "visible.txt").unwrap();
+        
+        assert!(!is_within_root(&canonical_root, &escape));
+        assert!(!is_within_root(&canonical_root, &served.path().join("escape")));
+        assert!(is_within_root(&canonical_root, &inner));
+        assert!(is_within_root(&canonical_root, &visible));
+    }
+}
@@ -0,0 +1,164 @@
+use warp::http::{HeaderValue, StatusCode};
+use warp::http::header::{ACCEPT_RANGES, CONTENT_RANGE};
+use warp::reply::Response;
+
+// An inclusive byte range within a body of known length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+// Inclusive, so a range is never empty
+#[allow(clippy::len_without_is_empty)]
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+// What to send in answer to a Range header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeOutcome {
+    // No range, or one to ignore: send the whole body
+    Full,
+    Partial(ByteRange),
+    // A valid range that doesn't overlap the body: 416
+    Unsatisfiable,
+}
+
+// Parse a single `bytes=` range spec against the body length. Malformed
+// specs, inverted ones like `bytes=5-2` and multi-range requests are
+// ignored, as HTTP allows, so the full body is sent; a range starting
+// past the end (or asking for the last 0 bytes) can't be satisfied
+pub fn parse_range(header: &str, total_len: u64) -> RangeOutcome {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec,
+        _ => return RangeOutcome::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => return RangeOutcome::Full,
+    };
+    
+    if start.is_empty() {
+        // Suffix range: the last N bytes
+        let suffix: u64 = match end.parse() {
+            Ok(suffix) => suffix,
+            Err(_) => return RangeOutcome::Full,
+        };
+        if suffix == 0 || total_len == 0 {
+            return RangeOutcome::Unsatisfiable;
+        }
+        return RangeOutcome::Partial(ByteRange {
+            start: total_len.saturating_sub(suffix),
+            end: total_len - 1,
+        });
+    }
+    
+    let start: u64 = match start.parse() {
+        Ok(start) => start,
+        Err(_) => return RangeOutcome::Full,
+    };
+    let end = if end.is_empty() {
+        None
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return RangeOutcome::Full,
+        }
+    };
+    if start >= total_len {
+        return RangeOutcome::Unsatisfiable;
+    }
+    
+    let last = total_len - 1;
+    RangeOutcome::Partial(ByteRange {
+        start,
+        end: end.map_or(last, |end| end.min(last)),
+    })
+}
+
+// Decide which range (if any) to serve. A request carrying `If-Range`
+// only gets a partial response when the validator still matches.
+pub fn requested_range(
+    range: Option<&str>,
+    if_range_matches: bool,
+    total_len: u64,
+) -> RangeOutcome {
+    match range {
+        Some(header) if if_range_matches => parse_range(header, total_len),
+        _ => RangeOutcome::Full,
+    }
+}
+
+// 416 for a range past the end, saying how long the body really is
+pub fn range_not_satisfiable(total_len: u64) -> Response {
+    let mut response = Response::default();
+    *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+    response.headers_mut().insert(
+        CONTENT_RANGE,
+        HeaderValue::from_str(&format!("bytes */{}", total_len)).unwrap(),
+    );
+    accept_ranges(&mut response);
+    response
+}
+
+// Turn a full response into a 206 for the given range by setting the status
+// and range headers. The body is expected to already contain only that range.
+pub fn mark_partial(response: &mut Response, range: ByteRange, total_len: u64) {
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    response.headers_mut().insert(
+        CONTENT_RANGE,
+        HeaderValue::from_str(&format!("bytes {}-{}/{}", range.start, range.end, total_len)).unwrap(),
+    );
+}
+
+// Advertise range support on a response
+pub fn accept_ranges(response: &mut Response) {
+    response.headers_mut().insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn partial(start: u64, end: u64) -> RangeOutcome {
+        RangeOutcome::Partial(ByteRange { start, end })
+    }
+    
+    #[test]
+    fn ranges_within_the_body_are_served() {
+        assert_eq!(parse_range("bytes=0-9", 100), partial(0, 9));
+        assert_eq!(parse_range("bytes=90-", 100), partial(90, 99));
+        assert_eq!(parse_range("bytes=-10", 100), partial(90, 99));
+        // An end past the body is clamped rather than refused
+        assert_eq!(parse_range("bytes=50-999", 100), partial(50, 99));
+    }
+    
+    #[test]
+    fn off_the_end_ranges_are_unsatisfiable() {
+        assert_eq!(parse_range("bytes=999999-", 100), RangeOutcome::Unsatisfiable);
+        assert_eq!(parse_range("bytes=100-200", 100), RangeOutcome::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 100), RangeOutcome::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeOutcome::Unsatisfiable);
+        
+        let response = range_not_satisfiable(100);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes */100");
+    }
+    
+    #[test]
+    fn inverted_and_malformed_ranges_get_the_full_body() {
+        assert_eq!(parse_range("bytes=50-10", 100), RangeOutcome::Full);
+        assert_eq!(parse_range("bytes=abc-", 100), RangeOutcome::Full);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), RangeOutcome::Full);
+        assert_eq!(parse_range("items=0-1", 100), RangeOutcome::Full);
+    }
+    
+    #[test]
+    fn failed_if_range_ignores_the_range() {
+        assert_eq!(requested_range(Some("bytes=999999-"), false, 100), RangeOutcome::Full);
+        assert_eq!(requested_range(Some("bytes=999999-"), true, 100), RangeOutcome::Unsatisfiable);
+    }
+}
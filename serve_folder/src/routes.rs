@@ -0,0 +1,829 @@
+use std::sync::Arc;
+use std::time::Instant;
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::reply::Response;
+
+use crate::models::{ArchivesDisabled, DirectoryQuery, FileQuery};
+use crate::state::ServerState;
+use crate::handlers::{handle_list, handle_list_stream, handle_stat, handle_thumbnail, handle_checksum, handle_manifest, handle_search, handle_tree, handle_upload, handle_mkdir, handle_delete, handle_stop, handle_health, handle_config, handle_version, handle_download_folder, handle_zip_selection, handle_zip_progress, handle_zip_events, handle_watch_events, handle_zip_init, handle_zip_preview, handle_zip_cancel, check_static_path, serve_directory, serve_raw_path_file, serve_single_file, with_static_content_type};
+use crate::web::serve_web_ui;
+use crate::openapi::handle_openapi;
+use crate::share::{handle_share, handle_shared_download, with_local_peer};
+use crate::errors::{handle_api_rejection, handle_rejection};
+use crate::logging::log_request;
+use crate::client_ip::with_client_ip;
+use crate::compression::compress_reply;
+use crate::cors::{cors_reply, preflight_reply, CorsPolicy};
+use crate::caching::conditionals;
+use crate::throttle::{throttle_reply, RateLimiter};
+use crate::ws::handle_ws_connection;
+
+// Every route the server answers, under --base-path, with compression,
+// throttling, CORS, --header and logging applied. All of it is set up from the
+// state's config, plus its access log if one was opened
+pub fn build_routes(state: ServerState) -> BoxedFilter<(impl Reply,)> {
+    let config = state.config();
+    let serve_path = config.root_path.clone();
+    let base_path = config.base_path.clone();
+    let verbose = config.verbose;
+    let compress = !config.no_compress;
+    let cors = CorsPolicy::new(&config.cors_origins).map(Arc::new);
+    let api_path_prefix = format!("{}/api/", config.base_path);
+    let rate_limiter = config.rate_limit.map(|bytes_per_sec| Arc::new(RateLimiter::new(bytes_per_sec)));
+    let trust_proxy = config.trust_proxy;
+    let access_log = state.access_log();
+    let extra_headers = Arc::new(config.extra_headers.clone());
+
+    // Create API routes
+    let api_stop = warp::path!("api" / "stop")
+        .and(warp::post())
+        .and(state.limit_requests())
+        .and(warp::body::json())
+        .and(state.with_state())
+        .and_then(handle_stop);
+
+    let api_health = warp::path!("api" / "health")
+        .and(warp::get())
+        .and(state.with_state())
+        .and_then(handle_health);
+
+    let api_config = warp::path!("api" / "config")
+        .and(warp::get())
+        .and(state.with_state())
+        .and_then(handle_config);
+
+    let api_version = warp::path!("api" / "version")
+        .and(warp::get())
+        .and_then(handle_version);
+
+    let api_openapi = warp::path!("api" / "openapi.json")
+        .and(warp::get())
+        .and(state.with_state())
+        .and_then(handle_openapi);
+
+    let api_list_stream = warp::path!("api" / "list" / "stream")
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_list_stream);
+
+    let api_list = warp::path!("api" / "list" / ..)
+        .and(warp::query())
+        .and(warp::header::optional::<String>("accept"))
+        .and(state.with_state())
+        .and_then(handle_list);
+
+    let api_stat = warp::path!("api" / "stat")
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_stat);
+
+    let api_thumb = warp::path!("api" / "thumb")
+        .and(warp::get())
+        .and(warp::query())
+        .and(conditionals())
+        .and(state.with_state())
+        .and_then(handle_thumbnail);
+
+    let api_checksum = warp::path!("api" / "checksum")
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_checksum);
+
+    let api_manifest = warp::path!("api" / "manifest")
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_manifest);
+
+    let api_search = warp::path!("api" / "search")
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_search);
+
+    let api_tree = warp::path!("api" / "tree")
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_tree);
+
+    let api_upload = warp::path!("api" / "upload")
+        .and(warp::post())
+        .and(warp::query())
+        .and(warp::header::optional::<u64>("content-length"))
+        .and(warp::multipart::form().max_length(None))
+        .and(state.with_state())
+        .and_then(handle_upload);
+
+    let api_mkdir = warp::path!("api" / "mkdir")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(state.with_state())
+        .and_then(handle_mkdir);
+
+    let api_delete = warp::path!("api" / "file")
+        .and(warp::delete())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_delete);
+
+    // Archive routes go through this, so --no-zip turns them all away
+    let archives = archives_enabled(!config.no_zip);
+
+    let api_download_folder = archives.clone()
+        .and(warp::path!("api" / "download" / "folder"))
+        .and(warp::get())
+        .and(state.limit_requests())
+        .and(warp::query())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .and(state.with_state())
+        .and_then(handle_download_folder);
+
+    let api_share = archives.clone()
+        .and(warp::path!("api" / "share"))
+        .and(warp::post())
+        .and(state.limit_requests())
+        .and(with_local_peer())
+        .and(warp::body::json())
+        .and(state.with_state())
+        .and_then(handle_share);
+
+    // Where share links point; real files of the same name win
+    let shared_download = archives.clone()
+        .and(warp::path!("download" / String))
+        .and(warp::get())
+        .and(state.limit_requests())
+        .and(state.with_state())
+        .and_then(handle_shared_download);
+
+    let api_zip_selection = archives.clone()
+        .and(warp::path!("api" / "zip"))
+        .and(warp::post())
+        .and(state.limit_requests())
+        .and(warp::body::json())
+        .and(state.with_state())
+        .and_then(handle_zip_selection);
+
+    let api_zip_progress = archives.clone()
+        .and(warp::path!("api" / "zip" / "progress"))
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_zip_progress);
+
+    let api_zip_events = archives.clone()
+        .and(warp::path!("api" / "zip" / "events"))
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_zip_events);
+
+    let api_watch = warp::path!("api" / "watch")
+        .and(warp::get())
+        .and(state.with_state())
+        .and_then(handle_watch_events);
+
+    let api_ws = archives.clone()
+        .and(warp::path!("api" / "ws"))
+        .and(warp::ws())
+        .and(state.with_state())
+        .map(|ws: warp::ws::Ws, state: ServerState| {
+            ws.on_upgrade(move |socket| handle_ws_connection(socket, state))
+        });
+
+    let api_zip_init = archives.clone()
+        .and(warp::path!("api" / "zip" / "init"))
+        .and(warp::get())
+        .and(state.limit_requests())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_zip_init);
+
+    let api_zip_preview = archives.clone()
+        .and(warp::path!("api" / "zip" / "preview"))
+        .and(warp::get())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_zip_preview);
+
+    let api_zip_cancel = archives
+        .and(warp::path!("api" / "zip" / "cancel"))
+        .and(warp::post())
+        .and(warp::query())
+        .and(state.with_state())
+        .and_then(handle_zip_cancel);
+
+    // CORS preflights, answered only when --cors is set
+    let preflight_enabled = cors.is_some();
+    let api_preflight = warp::options()
+        .and(warp::header::optional::<String>("access-control-request-headers"))
+        .and_then(move |request_headers: Option<String>| async move {
+            if preflight_enabled {
+                Ok(preflight_reply(request_headers.as_deref()))
+            } else {
+                Err(warp::reject::not_found())
+            }
+        });
+
+    // Serve web UI files
+    let web_ui = warp::path("webui")
+        .and(warp::get())
+        .and(warp::path::tail())
+        .and(conditionals())
+        .and(state.with_state())
+        .and_then(serve_web_ui);
+
+    // Serve a directory's index file when --index is set, or a plain HTML
+    // listing under --simple
+    let directories = warp::get()
+        .and(warp::path::full())
+        .and(warp::query::<DirectoryQuery>())
+        .and(conditionals())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .and(state.with_state())
+        .and_then(serve_directory);
+
+    // With a single file given, the root URL is that file
+    let single_file_root = warp::path::end()
+        .and(warp::get())
+        .and(warp::query::<FileQuery>())
+        .and(conditionals())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .and(state.with_state())
+        .and_then(serve_single_file);
+
+    // Redirect root to web UI
+    let web_ui_uri: warp::http::Uri = format!("{}/webui", base_path).parse().expect("base path is a valid URI");
+    let root_redirect = warp::path::end()
+        .and(warp::get())
+        .map(move || warp::redirect(web_ui_uri.clone()));
+
+    // Serve files directly, refusing paths that escape the root via symlinks
+    let static_files = warp::path::full()
+        .and(state.with_state())
+        .and_then(check_static_path)
+        .untuple_one()
+        .and(conditionals())
+        .and(warp::query::<FileQuery>())
+        .and(warp::fs::dir(serve_path))
+        .map(with_static_content_type);
+
+    // Files whose names aren't valid UTF-8, which warp::fs::dir can't serve
+    let raw_path_files = warp::get()
+        .and(warp::path::full())
+        .and(warp::query::<FileQuery>())
+        .and(conditionals())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .and(state.with_state())
+        .and_then(serve_raw_path_file);
+
+    // API routes answer every failure under /api with a JSON error
+    let api_routes = api_prefix()
+        .and(
+            api_stop
+                .or(api_health)
+                .or(api_config)
+                .or(api_version)
+                .or(api_openapi)
+                .or(api_list_stream)
+                .or(api_list)
+                .or(api_stat)
+                .or(api_thumb)
+                .or(api_checksum)
+                .or(api_manifest)
+                .or(api_search)
+                .or(api_tree)
+                .or(api_upload)
+                .or(api_mkdir)
+                .or(api_delete)
+                .or(api_download_folder)
+                .or(api_share)
+                .or(api_zip_selection)
+                .or(api_zip_progress)
+                .or(api_zip_events)
+                .or(api_watch)
+                .or(api_ws)
+                .or(api_zip_init)
+                .or(api_zip_preview)
+                .or(api_zip_cancel)
+                .or(api_preflight)
+                .recover(handle_api_rejection)
+        )
+        .boxed();
+
+    // Create combined routes, all under --base-path
+    let not_found_base = base_path.clone();
+    let routes = base_path_prefix(&base_path)
+        .and(
+            api_routes
+                .or(web_ui)
+                .or(single_file_root)
+                .or(directories)
+                .or(root_redirect)
+                .or(static_files)
+                .or(raw_path_files)
+                .or(shared_download)
+        )
+        .recover(move |err| handle_rejection(err, not_found_base.clone()))
+        // Boxing here and on the API routes erases the route tree's type,
+        // which is otherwise nested deep enough for a fresh build to hit
+        // the compiler's query limit
+        .boxed();
+
+    // Compress text responses for clients that accept it, pace them under
+    // --rate-limit, add CORS headers to API replies under --cors and the
+    // --header ones to everything, then log
+    state.track_activity()
+        .and(warp::any().map(Instant::now))
+        .and(warp::method())
+        .and(with_client_ip(trust_proxy))
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("origin"))
+        .and(routes)
+        .map(move |started, method: warp::http::Method, client, accept_encoding, path: warp::path::FullPath, origin: Option<String>, reply| {
+            let response = throttle_reply(compress_reply(reply, accept_encoding, compress), rate_limiter.clone());
+            let response = if path.as_str().starts_with(&api_path_prefix) {
+                cors_reply(response, origin.as_deref(), cors.as_deref())
+            } else {
+                response
+            };
+            let response = with_extra_headers(response, &extra_headers);
+            match &access_log {
+                Some(access_log) => access_log.record(response, client, method.to_string(), path.as_str().to_string(), started),
+                None => response,
+            }
+        })
+        .with(warp::log::custom(move |info| log_request(info, verbose, trust_proxy)))
+        .boxed()
+}
+
+// Set the --header headers, replacing any of the same name the route set.
+// A name given more than once is sent with each of its values
+fn with_extra_headers(mut response: Response, extra_headers: &[(HeaderName, HeaderValue)]) -> Response {
+    for (i, (name, value)) in extra_headers.iter().enumerate() {
+        if extra_headers[..i].iter().any(|(earlier, _)| earlier == name) {
+            response.headers_mut().append(name.clone(), value.clone());
+        } else {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+// Match the --base-path segments, if any, ahead of every route
+fn base_path_prefix(base_path: &str) -> BoxedFilter<()> {
+    base_path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(warp::any().boxed(), |filter, segment| filter.and(warp::path(segment.to_string())).boxed())
+}
+
+// Pass when `on`. Otherwise reject with ArchivesDisabled, a 404 that
+// outranks the method mismatches of other routes on the same path
+fn archives_enabled(on: bool) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if on {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(ArchivesDisabled))
+            }
+        })
+        .untuple_one()
+}
+
+// Match (without consuming) any path whose first segment is `api`
+fn api_prefix() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::peek()
+        .and_then(|peek: warp::path::Peek| async move {
+            if peek.segments().next() == Some("api") {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+    use crate::config::Config;
+    
+    // The real server over `root` on an ephemeral port, stopped through
+    // /api/stop like the real one
+    fn start_server(root: &Path) -> (SocketAddr, JoinHandle<()>) {
+        start_server_with(config_for(root))
+    }
+    
+    fn config_for(root: &Path) -> Config {
+        Config {
+            root_path: root.to_path_buf(),
+            canonical_root: fs::canonicalize(root).unwrap(),
+            ..Config::default()
+        }
+    }
+    
+    fn start_server_with(config: Config) -> (SocketAddr, JoinHandle<()>) {
+        let state = ServerState::new(config);
+        let (tx, rx) = oneshot::channel::<()>();
+        state.set_shutdown_tx(tx);
+        let (addr, server) = warp::serve(build_routes(state))
+            .bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
+                let _ = rx.await;
+            });
+        (addr, tokio::spawn(server))
+    }
+    
+    fn sample_tree() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a.txt"), "alpha").unwrap();
+        fs::create_dir(root.path().join("sub")).unwrap();
+        fs::write(root.path().join("sub").join("b.txt"), "bravo").unwrap();
+        root
+    }
+    
+    #[tokio::test]
+    async fn lists_a_directory() {
+        let root = sample_tree();
+        let (addr, _server) = start_server(root.path());
+        
+        let response = reqwest::get(format!("http://{}/api/list?path=", addr)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let json: serde_json::Value = response.json().await.unwrap();
+        let names: Vec<&str> = json["entries"].as_array().unwrap().iter()
+            .map(|entry| entry["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["sub", "a.txt"]);
+        
+        let response = reqwest::get(format!("http://{}/api/list?path=missing", addr)).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+    
+    #[tokio::test]
+    async fn list_stream_sends_one_entry_per_line() {
+        let root = sample_tree();
+        fs::write(root.path().join(".secret"), "hidden").unwrap();
+        // Enough names to span several chunks
+        for i in 0..1000 {
+            fs::write(root.path().join("sub").join(format!("file_{:04}.txt", i)), "").unwrap();
+        }
+        let (addr, _server) = start_server(root.path());
+        
+        let response = reqwest::get(format!("http://{}/api/list/stream?path=", addr)).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+        let mut names: Vec<String> = response.text().await.unwrap().lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["name"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "sub"]);
+        
+        let body = reqwest::get(format!("http://{}/api/list/stream?path=sub", addr)).await.unwrap()
+            .text().await.unwrap();
+        assert_eq!(body.lines().count(), 1001);
+        assert!(body.lines().any(|line| line.contains(r#""path":"sub/file_0999.txt""#)));
+        
+        let response = reqwest::get(format!("http://{}/api/list/stream?path=missing", addr)).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+    
+    #[tokio::test]
+    async fn header_option_applies_to_every_response() {
+        let root = sample_tree();
+        let args: Vec<String> = [
+            root.path().to_str().unwrap(),
+            "--header", "X-Frame-Options: DENY",
+            "--header", "Cache-Control: no-store",
+            "--header", "Link: </a>; rel=preload",
+            "--header", "Link: </b>; rel=preload",
+        ].iter().map(|arg| arg.to_string()).collect();
+        let (addr, _server) = start_server_with(Config::from_args(&args).unwrap());
+        
+        for path in ["a.txt", "api/list?path=", "missing"] {
+            let response = reqwest::get(format!("http://{}/{}", addr, path)).await.unwrap();
+            assert_eq!(response.headers()["x-frame-options"], "DENY", "{}", path);
+            let cache_control: Vec<_> = response.headers().get_all("cache-control").iter().collect();
+            assert_eq!(cache_control, ["no-store"], "{}", path);
+            assert_eq!(response.headers().get_all("link").iter().count(), 2, "{}", path);
+        }
+        
+        for bad in ["NoColon", "Bad Name: x", "X-Test: line\nbreak", "Content-Length: 1"] {
+            let args = [root.path().to_str().unwrap().to_string(), "--header".to_string(), bad.to_string()];
+            assert!(Config::from_args(&args).is_err(), "{}", bad);
+        }
+    }
+    
+    #[tokio::test]
+    async fn single_file_mode_serves_ranges() {
+        let root = tempfile::tempdir().unwrap();
+        let file_path = root.path().join("movie.bin");
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&file_path, &data).unwrap();
+        let (addr, _server) = start_server_with(Config::from_args(&[file_path.to_str().unwrap().to_string()]).unwrap());
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/", addr);
+        
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["accept-ranges"], "bytes");
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        
+        let response = client.get(&url).header("range", "bytes=1000-1999").send().await.unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.headers()["content-range"], "bytes 1000-1999/5000");
+        assert_eq!(response.bytes().await.unwrap(), data[1000..2000]);
+        
+        let response = client.get(&url).header("range", "bytes=4000-").header("if-range", &etag).send().await.unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.bytes().await.unwrap(), data[4000..]);
+        
+        // A stale validator gets the whole file instead
+        let response = client.get(&url).header("range", "bytes=4000-").header("if-range", "\"stale\"").send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.bytes().await.unwrap().len(), 5000);
+        
+        let response = client.get(&url).header("range", "bytes=5000-").send().await.unwrap();
+        assert_eq!(response.status(), 416);
+        assert_eq!(response.headers()["content-range"], "bytes */5000");
+    }
+    
+    #[tokio::test]
+    async fn root_config_file_is_never_served_or_replaced() {
+        let root = sample_tree();
+        fs::write(root.path().join("serve-folder.json"), r#"{"port": 9000}"#).unwrap();
+        let (addr, _server) = start_server_with(Config { allow_upload: true, show_hidden: true, ..config_for(root.path()) });
+        let client = reqwest::Client::new();
+        
+        for name in ["serve-folder.json", "SERVE-FOLDER.JSON"] {
+            let response = client.get(format!("http://{}/{}", addr, name)).send().await.unwrap();
+            assert_eq!(response.status(), 404, "{}", name);
+        }
+        let listing: serde_json::Value = client.get(format!("http://{}/api/list?path=", addr)).send().await.unwrap()
+            .json().await.unwrap();
+        assert!(listing["entries"].as_array().unwrap().iter().all(|entry| entry["name"] != "serve-folder.json"));
+        
+        let upload = "--B\r\nContent-Disposition: form-data; name=\"file\"; filename=\"serve-folder.json\"\r\n\r\n\
+                      {\"allow-delete\": true}\r\n--B--\r\n";
+        let response = client.post(format!("http://{}/api/upload?path=", addr))
+            .header("content-type", "multipart/form-data; boundary=B")
+            .body(upload)
+            .send().await.unwrap();
+        assert_eq!(response.status(), 400);
+        let response = client.post(format!("http://{}/api/mkdir", addr))
+            .json(&serde_json::json!({"path": "", "name": "serve-folder.json"}))
+            .send().await.unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(fs::read_to_string(root.path().join("serve-folder.json")).unwrap(), r#"{"port": 9000}"#);
+        
+        // Only the root's copy is read as options
+        let response = client.post(format!("http://{}/api/mkdir", addr))
+            .json(&serde_json::json!({"path": "sub", "name": "serve-folder.json"}))
+            .send().await.unwrap();
+        assert_eq!(response.status(), 201);
+    }
+    
+    #[tokio::test]
+    async fn downloads_a_folder_as_zip() {
+        let root = sample_tree();
+        let (addr, _server) = start_server(root.path());
+        
+        let response = reqwest::get(format!("http://{}/api/download/folder?path=sub", addr)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/zip");
+        // Browsers need the length up front to show download progress
+        let content_length: usize = response.headers()["content-length"].to_str().unwrap().parse().unwrap();
+        let body = response.bytes().await.unwrap();
+        assert_eq!(content_length, body.len());
+        
+        let mut archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("b.txt").unwrap(), &mut contents).unwrap();
+        assert_eq!(contents, "bravo");
+    }
+    
+    #[tokio::test]
+    async fn small_folders_are_zipped_in_memory() {
+        let root = sample_tree();
+        let (addr, _server) = start_server_with(Config { mem_zip_limit: Some(1024), ..config_for(root.path()) });
+        let url = format!("http://{}/api/download/folder?path=sub", addr);
+        
+        let body = reqwest::get(&url).await.unwrap().bytes().await.unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(body.clone())).unwrap();
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("b.txt").unwrap(), &mut contents).unwrap();
+        assert_eq!(contents, "bravo");
+        
+        // Resuming works the same as for archives on disk
+        let response = reqwest::Client::new().get(&url).header("range", "bytes=10-").send().await.unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.bytes().await.unwrap(), body.slice(10..));
+    }
+    
+    #[tokio::test]
+    async fn no_zip_leaves_only_files() {
+        let root = sample_tree();
+        let (addr, _server) = start_server_with(Config { no_zip: true, ..config_for(root.path()) });
+        let client = reqwest::Client::new();
+        
+        let response = client.get(format!("http://{}/api/download/folder?path=sub", addr)).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        let response = client.post(format!("http://{}/api/zip", addr))
+            .json(&serde_json::json!({"paths": ["a.txt"]}))
+            .send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        
+        let response = client.get(format!("http://{}/sub/b.txt", addr)).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "bravo");
+        let config: serde_json::Value = client.get(format!("http://{}/api/config", addr)).send().await.unwrap()
+            .json().await.unwrap();
+        assert_eq!(config["can_zip"], false);
+    }
+    
+    #[tokio::test]
+    async fn no_listing_still_serves_files() {
+        let root = sample_tree();
+        let (addr, _server) = start_server_with(Config { no_listing: true, ..config_for(root.path()) });
+        
+        for endpoint in ["list?path=", "list/stream?path=", "tree?path=", "search?path=&q=a", "manifest?path=", "zip/preview?path=",
+                         "zip/init?path=sub", "download/folder?path=sub"] {
+            let response = reqwest::get(format!("http://{}/api/{}", addr, endpoint)).await.unwrap();
+            assert_eq!(response.status(), 403, "{}", endpoint);
+        }
+        
+        let response = reqwest::get(format!("http://{}/sub/b.txt", addr)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "bravo");
+        
+        let stat: serde_json::Value = reqwest::get(format!("http://{}/api/stat?path=sub", addr)).await.unwrap()
+            .json().await.unwrap();
+        assert!(stat["child_count"].is_null());
+        
+        let config: serde_json::Value = reqwest::get(format!("http://{}/api/config", addr)).await.unwrap()
+            .json().await.unwrap();
+        assert_eq!(config["can_list"], false);
+        assert_eq!(config["can_zip"], false);
+    }
+    
+    #[tokio::test]
+    async fn share_links_download_a_folder_once() {
+        let root = sample_tree();
+        let (addr, _server) = start_server(root.path());
+        let client = reqwest::Client::new();
+        
+        let share: serde_json::Value = client.post(format!("http://{}/api/share", addr))
+            .json(&serde_json::json!({"path": "sub", "expires_in": 60}))
+            .send().await.unwrap()
+            .json().await.unwrap();
+        assert_eq!(share["success"], true);
+        let url = format!("http://{}{}", addr, share["url"].as_str().unwrap());
+        
+        // A download that fails doesn't use the link up
+        fs::rename(root.path().join("sub"), root.path().join("moved")).unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 404);
+        fs::rename(root.path().join("moved"), root.path().join("sub")).unwrap();
+        
+        // Ranges aren't offered, so a ranged request gets the whole archive
+        let response = client.get(&url).header("range", "bytes=0-99").send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(!response.headers().contains_key("accept-ranges"));
+        let archive = zip::ZipArchive::new(Cursor::new(response.bytes().await.unwrap())).unwrap();
+        assert!(archive.file_names().any(|name| name == "b.txt"));
+        
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 403);
+        let tampered = format!("http://{}/download/00{}", addr, share["token"].as_str().unwrap());
+        assert_eq!(client.get(tampered).send().await.unwrap().status(), 403);
+    }
+    
+    #[tokio::test]
+    async fn share_links_ignore_forwarded_addresses() {
+        let root = sample_tree();
+        let routes = build_routes(ServerState::new(Config { trust_proxy: true, ..config_for(root.path()) }));
+        let share = |remote: &str, forwarded_for: Option<&str>| {
+            let mut request = warp::test::request()
+                .method("POST")
+                .path("/api/share")
+                .remote_addr(remote.parse().unwrap())
+                .json(&serde_json::json!({"path": "sub"}));
+            if let Some(forwarded_for) = forwarded_for {
+                request = request.header("x-forwarded-for", forwarded_for);
+            }
+            request
+        };
+        
+        // A remote client claiming to be local
+        let response = share("192.168.1.20:5000", Some("127.0.0.1")).reply(&routes).await;
+        assert_eq!(response.status(), 403);
+        // A remote client reaching a proxy on the server's machine
+        let response = share("127.0.0.1:5000", Some("192.168.1.20")).reply(&routes).await;
+        assert_eq!(response.status(), 403);
+        let response = share("127.0.0.1:5000", None).reply(&routes).await;
+        assert_eq!(response.status(), 200);
+    }
+    
+    #[tokio::test]
+    async fn max_file_size_applies_to_listing_fetch_and_zip() {
+        let root = sample_tree();
+        fs::write(root.path().join("exact.bin"), [0; 10]).unwrap();
+        fs::write(root.path().join("over.bin"), [0; 11]).unwrap();
+        let (addr, _server) = start_server_with(Config { max_file_size: Some(10), ..config_for(root.path()) });
+        
+        let listing: serde_json::Value = reqwest::get(format!("http://{}/api/list?path=", addr)).await.unwrap()
+            .json().await.unwrap();
+        let names: Vec<&str> = listing["entries"].as_array().unwrap().iter()
+            .map(|entry| entry["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"exact.bin"));
+        assert!(!names.contains(&"over.bin"));
+        
+        assert_eq!(reqwest::get(format!("http://{}/exact.bin", addr)).await.unwrap().status(), 200);
+        assert_eq!(reqwest::get(format!("http://{}/over.bin", addr)).await.unwrap().status(), 403);
+        
+        let body = reqwest::get(format!("http://{}/api/download/folder?path=", addr)).await.unwrap()
+            .bytes().await.unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        assert!(archive.file_names().any(|name| name == "exact.bin"));
+        assert!(!archive.file_names().any(|name| name == "over.bin"));
+    }
+    
+    // The same calls the web UI's download button makes
+    #[tokio::test]
+    async fn folder_download_follows_the_web_ui_flow() {
+        let root = sample_tree();
+        let (addr, _server) = start_server(root.path());
+        
+        let init: serde_json::Value = reqwest::get(format!("http://{}/api/zip/init?path=sub", addr)).await.unwrap()
+            .json().await.unwrap();
+        assert_eq!(init["success"], true);
+        let operation_id = init["operationId"].as_str().unwrap();
+        
+        let progress = reqwest::get(format!("http://{}/api/zip/progress?id={}", addr, operation_id)).await.unwrap();
+        assert_eq!(progress.status(), 200);
+        
+        let url = format!("http://{}/api/download/folder?path=sub&operation_id={}&format=zip", addr, operation_id);
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = response.bytes().await.unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        assert!(archive.file_names().any(|name| name == "b.txt"));
+    }
+    
+    #[tokio::test]
+    async fn operation_ids_must_be_plain() {
+        let root = sample_tree();
+        let (addr, _server) = start_server(root.path());
+        let client = reqwest::Client::new();
+        
+        let long_id = "a".repeat(65);
+        for operation_id in ["bad%0Aid", "caf%C3%A9", long_id.as_str()] {
+            let url = format!("http://{}/api/download/folder?path=sub&operation_id={}", addr, operation_id);
+            let response = reqwest::get(url).await.unwrap();
+            assert_eq!(response.status(), 400, "{}", operation_id);
+        }
+        
+        let response = client.post(format!("http://{}/api/zip", addr))
+            .json(&serde_json::json!({"paths": ["a.txt"], "operation_id": "two words"}))
+            .send().await.unwrap();
+        assert_eq!(response.status(), 400);
+        
+        let url = format!("http://{}/api/download/folder?path=sub&operation_id=ui-op_1", addr);
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["x-operation-id"], "ui-op_1");
+    }
+    
+    #[tokio::test]
+    async fn stop_needs_confirmation_then_shuts_down() {
+        let root = sample_tree();
+        let (addr, server) = start_server(root.path());
+        let client = reqwest::Client::new();
+        let stop_url = format!("http://{}/api/stop", addr);
+        
+        let response = client.post(&stop_url).json(&serde_json::json!({"confirm": false})).send().await.unwrap();
+        assert_eq!(response.status(), 400);
+        
+        let response = client.post(&stop_url).json(&serde_json::json!({"confirm": true})).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let json: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(json["success"], true);
+        
+        tokio::time::timeout(Duration::from_secs(5), server).await
+            .expect("server stopped")
+            .unwrap();
+    }
+}
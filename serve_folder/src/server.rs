@@ -0,0 +1,178 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::config::Config;
+use crate::state::ServerState;
+use crate::access_log::AccessLog;
+use crate::banner::{local_url, print_banner};
+use crate::routes::build_routes;
+use crate::watch::watch_root;
+
+// How often stale ZIP progress is swept, and how old an entry must be to go
+const PROGRESS_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const PROGRESS_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+// Ports tried with --auto-port, starting at the requested one
+const AUTO_PORT_ATTEMPTS: u16 = 100;
+
+// Serve `config` until /api/stop, Ctrl+C, SIGTERM or --idle-timeout stops
+// it. Errs if the port or the access log can't be opened
+pub async fn run_server(config: Config) -> Result<(), String> {
+    let quiet = config.quiet;
+    let single_file = config.single_file.clone();
+    let addr = available_address(config.bind_address(), config.auto_port, quiet)?;
+    let base_path = config.base_path.clone();
+    let show_qr = config.qr;
+    let open_browser = config.open;
+    let access_log = match &config.access_log {
+        Some(path) => match AccessLog::open(path, config.log_max_size) {
+            Ok(access_log) => Some(Arc::new(access_log)),
+            Err(e) => return Err(format!("Cannot open access log {}: {}", path.display(), e)),
+        },
+        None => None,
+    };
+
+    // Create shared state for server control
+    let state = ServerState::new(config);
+    state.set_access_log(access_log);
+
+    // Create a channel for server shutdown
+    let (tx, rx) = oneshot::channel::<()>();
+    state.set_shutdown_tx(tx);
+
+    // Kept until the server stops, since dropping it stops the watching
+    let _watcher = if state.config().watch {
+        let watcher = watch_root(state.clone())
+            .map_err(|e| format!("Cannot watch {}: {}", state.config().root_path.display(), e))?;
+        Some(watcher)
+    } else {
+        None
+    };
+
+    // Periodically evict progress entries for ZIPs that were never downloaded
+    let sweep_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PROGRESS_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweep_state.evict_stale_progress(PROGRESS_MAX_AGE);
+        }
+    });
+
+    // Stop the server once nothing has been requested for --idle-timeout
+    if let Some(idle_timeout) = state.config().idle_timeout {
+        let idle_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let idle_for = idle_state.idle_for();
+                if idle_for >= idle_timeout {
+                    if !quiet {
+                        println!("No requests for {} minute(s)", idle_timeout.as_secs() / 60);
+                    }
+                    if let Some(tx) = idle_state.take_shutdown_tx() {
+                        let _ = tx.send(());
+                    }
+                    break;
+                }
+                tokio::time::sleep(idle_timeout - idle_for).await;
+            }
+        });
+    }
+
+    let routes = build_routes(state.clone());
+
+    if !quiet {
+        if let Some(single_file) = &single_file {
+            println!("Sharing only {}", single_file.display());
+        }
+        print_banner(addr, &base_path, show_qr);
+    }
+
+    // Run server with graceful shutdown
+    let server = match warp::serve(routes).try_bind_with_graceful_shutdown(addr, async move {
+        shutdown_signal(rx).await;
+        if !quiet {
+            println!("Server shutting down");
+        }
+    }) {
+        Ok((_, server)) => server,
+        // Another process grabbed the port since it was checked
+        Err(e) => return Err(format!("Could not listen on {}: {}", addr, e)),
+    };
+
+    // The socket is bound by now, so the browser won't race the listener
+    if open_browser {
+        let url = local_url(addr, &base_path);
+        std::thread::spawn(move || {
+            // Headless machines have no browser; that's fine
+            let _ = webbrowser::open(&url);
+        });
+    }
+
+    // Run the server
+    server.await;
+    Ok(())
+}
+
+// Resolve when /api/stop fires the oneshot, on Ctrl+C, or on SIGTERM
+async fn shutdown_signal(stop_rx: oneshot::Receiver<()>) {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            // No signal handler available; rely on the other triggers
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = stop_rx => {}
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// Check the address can be bound before building the server, so a busy
+// port gets a clear message instead of a panic inside warp. With
+// --auto-port, the next ports up are tried and the first free one is used
+fn available_address(addr: SocketAddr, auto_port: bool, quiet: bool) -> Result<SocketAddr, String> {
+    let attempts = if auto_port { AUTO_PORT_ATTEMPTS } else { 1 };
+    let mut candidate = addr;
+    for _ in 0..attempts {
+        match TcpListener::bind(candidate) {
+            // Dropping the listener frees the port again for warp
+            Ok(_) => {
+                if candidate != addr && !quiet {
+                    println!("Port {} is in use; using port {} instead", addr.port(), candidate.port());
+                }
+                return Ok(candidate);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                match candidate.port().checked_add(1) {
+                    Some(port) => candidate.set_port(port),
+                    None => break,
+                }
+            }
+            Err(e) => return Err(format!("Could not listen on {}: {}", candidate, e)),
+        }
+    }
+    
+    if auto_port {
+        Err(format!("No free port found between {} and {}", addr.port(), candidate.port()))
+    } else {
+        Err(format!("Port {} is already in use. Choose another with --port <port>, or pass --auto-port to use the next free one", addr.port()))
+    }
+}
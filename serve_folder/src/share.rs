@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use warp::{Filter, Reply, Rejection, http::StatusCode, hyper::Body};
+use warp::http::header::{ACCEPT_RANGES, CONTENT_LENGTH};
+
+use crate::handlers::{handle_download_folder, json_message, listing_forbidden};
+use crate::models::{DownloadQuery, ShareRequest};
+use crate::paths::encode_rel_path;
+use crate::state::ServerState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// How long a share link lasts unless the request asks for less
+const DEFAULT_SHARE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_SHARE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// Why a share token was refused
+#[derive(Debug, PartialEq)]
+pub enum ShareError {
+    Malformed,
+    BadSignature,
+    Expired,
+    AlreadyUsed,
+}
+
+impl ShareError {
+    fn message(&self) -> &'static str {
+        match self {
+            ShareError::Malformed | ShareError::BadSignature => "This download link is not valid",
+            ShareError::Expired => "This download link has expired",
+            ShareError::AlreadyUsed => "This download link has already been used",
+        }
+    }
+}
+
+// Signs one-time folder download links and remembers which are unused.
+// The secret is made at startup, so a restart invalidates every link
+pub struct ShareTokens {
+    secret: [u8; 32],
+    // Nonce of each unused link, with its expiry in Unix seconds
+    issued: Mutex<HashMap<String, u64>>,
+}
+
+impl ShareTokens {
+    pub fn new() -> Self {
+        Self::with_secret(random_bytes())
+    }
+    
+    pub fn with_secret(secret: [u8; 32]) -> Self {
+        Self {
+            secret,
+            issued: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    // A token for rel_path that lasts `ttl`, and when it expires. The
+    // token is the hex of "expiry:nonce:path", a dot, then the hex HMAC
+    pub fn issue(&self, rel_path: &str, ttl: Duration) -> (String, u64) {
+        let now = now_secs();
+        let expires_at = now + ttl.as_secs();
+        let nonce = hex::encode(&random_bytes()[..16]);
+        let payload = format!("{}:{}:{}", expires_at, nonce, rel_path);
+        
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let token = format!("{}.{}", hex::encode(&payload), hex::encode(mac.finalize().into_bytes()));
+        
+        let mut issued = self.issued.lock().unwrap();
+        issued.retain(|_, expiry| *expiry > now);
+        issued.insert(nonce, expires_at);
+        (token, expires_at)
+    }
+    
+    // Check a token without using it up, returning the path it was issued
+    // for and the nonce to pass to use_up
+    pub fn check(&self, token: &str) -> Result<(String, String), ShareError> {
+        let (payload, signature) = token.split_once('.').ok_or(ShareError::Malformed)?;
+        let payload = hex::decode(payload).ok()
+            .and_then(|payload| String::from_utf8(payload).ok())
+            .ok_or(ShareError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| ShareError::Malformed)?;
+        
+        // Checked before anything in the payload is trusted
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| ShareError::BadSignature)?;
+        
+        let mut parts = payload.splitn(3, ':');
+        let (expires_at, nonce, rel_path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(expires_at), Some(nonce), Some(rel_path)) => (expires_at, nonce, rel_path),
+            _ => return Err(ShareError::Malformed),
+        };
+        let expires_at: u64 = expires_at.parse().map_err(|_| ShareError::Malformed)?;
+        if expires_at <= now_secs() {
+            return Err(ShareError::Expired);
+        }
+        if !self.issued.lock().unwrap().contains_key(nonce) {
+            return Err(ShareError::AlreadyUsed);
+        }
+        Ok((rel_path.to_string(), nonce.to_string()))
+    }
+    
+    // Retire a checked token's nonce, so the link stops working
+    pub fn use_up(&self, nonce: &str) {
+        self.issued.lock().unwrap().remove(nonce);
+    }
+    
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any length")
+    }
+}
+
+impl Default for ShareTokens {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Whether a request came straight from the machine the server runs on.
+// Only the socket's peer counts, never X-Forwarded-For, even under
+// --trust-proxy: proxies pass on whatever the client put there. A request
+// that went through a proxy at all isn't local, even if the proxy is
+pub fn with_local_peer() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::header::optional::<String>("forwarded"))
+        .map(|remote: Option<SocketAddr>, forwarded_for: Option<String>, forwarded: Option<String>| {
+            forwarded_for.is_none() && forwarded.is_none() && remote.is_some_and(|remote| is_local(remote.ip()))
+        })
+}
+
+// Mint a link to download a folder as a ZIP. Only the machine the server
+// runs on may do this, since there are no accounts to tell an admin by
+pub async fn handle_share(local: bool, request: ShareRequest, state: ServerState) -> Result<impl Reply, Rejection> {
+    if !local {
+        return Ok(json_message(StatusCode::FORBIDDEN, false, "Share links can only be made on the server's own machine"));
+    }
+    // The link would only answer 403 under --no-listing
+    if let Some(response) = listing_forbidden(&state) {
+        return Ok(response);
+    }
+    
+    let full_path = match state.resolve_path(&request.path) {
+        Some(path) if path.is_dir() => path,
+        _ => return Err(warp::reject::not_found()),
+    };
+    let ttl = request.expires_in.map(Duration::from_secs).unwrap_or(DEFAULT_SHARE_TTL);
+    if ttl.is_zero() || ttl > MAX_SHARE_TTL {
+        return Ok(json_message(StatusCode::BAD_REQUEST, false, "expires_in must be between 1 second and 7 days"));
+    }
+    
+    let root_path = state.get_root_path();
+    let rel_path = encode_rel_path(full_path.strip_prefix(&root_path).unwrap_or(&full_path));
+    let (token, expires_at) = state.share_tokens().issue(&rel_path, ttl);
+    
+    Ok(warp::reply::json(&serde_json::json!({
+        "success": true,
+        "url": format!("{}/download/{}", state.config().base_path, token),
+        "token": token,
+        "expires_at": expires_at
+    })).into_response())
+}
+
+// Download the folder a share link was made for, once. The link is used
+// up when the whole archive has been sent, so a failed build or a dropped
+// connection can be retried until it expires. Ranges aren't offered: a
+// resumed download would need the same bytes as the first, which only
+// --deterministic promises
+pub async fn handle_shared_download(token: String, state: ServerState) -> Result<warp::reply::Response, Rejection> {
+    let (rel_path, nonce) = match state.share_tokens().check(&token) {
+        Ok(checked) => checked,
+        Err(e) => return Ok(json_message(StatusCode::FORBIDDEN, false, e.message())),
+    };
+    
+    let query = DownloadQuery {
+        path: rel_path,
+        operation_id: None,
+        format: None,
+    };
+    let mut response = handle_download_folder(query, None, None, state.clone()).await?.into_response();
+    if response.status() != StatusCode::OK {
+        return Ok(response);
+    }
+    response.headers_mut().remove(ACCEPT_RANGES);
+    let total_len = response.headers().get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    
+    let (parts, body) = response.into_parts();
+    let mut sent = 0;
+    let body = body.inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            sent += chunk.len() as u64;
+            if sent >= total_len {
+                state.share_tokens().use_up(&nonce);
+            }
+        }
+    });
+    Ok(warp::reply::Response::from_parts(parts, Body::wrap_stream(body)))
+}
+
+// Loopback, including IPv4 loopback reached over an IPv6 socket
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback(),
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(v6.is_loopback(), |v4| v4.is_loopback()),
+    }
+}
+
+fn random_bytes() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("the OS provides random bytes");
+    bytes
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn tokens_work_until_used_up() {
+        let tokens = ShareTokens::with_secret([7; 32]);
+        let (token, _) = tokens.issue("photos/2024", Duration::from_secs(60));
+        
+        let (rel_path, nonce) = tokens.check(&token).unwrap();
+        assert_eq!(rel_path, "photos/2024");
+        assert_eq!(tokens.check(&token), Ok((rel_path, nonce.clone())));
+        tokens.use_up(&nonce);
+        assert_eq!(tokens.check(&token), Err(ShareError::AlreadyUsed));
+    }
+    
+    #[test]
+    fn tampered_and_foreign_tokens_are_refused() {
+        let tokens = ShareTokens::with_secret([7; 32]);
+        let (token, _) = tokens.issue("photos", Duration::from_secs(60));
+        let (payload, signature) = token.split_once('.').unwrap();
+        
+        // Same signature over a different path
+        let forged = hex::encode(String::from_utf8(hex::decode(payload).unwrap()).unwrap().replace("photos", "secret"));
+        assert_eq!(tokens.check(&format!("{}.{}", forged, signature)), Err(ShareError::BadSignature));
+        assert_eq!(tokens.check("not-a-token"), Err(ShareError::Malformed));
+        
+        let other_server = ShareTokens::with_secret([8; 32]);
+        assert_eq!(other_server.check(&token), Err(ShareError::BadSignature));
+    }
+    
+    #[test]
+    fn expired_tokens_are_refused() {
+        let tokens = ShareTokens::with_secret([7; 32]);
+        let (token, _) = tokens.issue("photos", Duration::ZERO);
+        assert_eq!(tokens.check(&token), Err(ShareError::Expired));
+    }
+    
+    #[test]
+    fn only_loopback_is_local() {
+        assert!(is_local("127.0.0.1".parse().unwrap()));
+        assert!(is_local("::1".parse().unwrap()));
+        assert!(is_local("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!is_local("192.168.1.20".parse().unwrap()));
+    }
+}
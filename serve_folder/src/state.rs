@@ -1,51 +1,218 @@
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::collections::HashMap;
-use tokio::sync::oneshot;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, oneshot, OwnedSemaphorePermit, Semaphore};
 use warp::Filter;
 
-use crate::models::ZipProgress;
+use crate::access_log::AccessLog;
+use crate::client_ip::with_client_ip;
+use crate::config::{Config, CONFIG_FILE_NAME};
+use crate::ignore_file::IgnoreFile;
+use crate::ip_limit::IpRateLimiter;
+use crate::listing_cache::ListingCache;
+use crate::models::{FileEntry, RateLimited, ZipProgress};
+use crate::paths::{is_hidden, is_within_root, resolve_safe_path};
+use crate::share::ShareTokens;
+use crate::zip::CompressionLevel;
 
+// Each piece of mutable state has its own lock so that a busy ZIP progress
+// tracker never blocks requests that only need the (immutable) config.
+// When both maps are needed, zip_progress is always locked first.
 pub struct ServerStateInner {
-    pub shutdown_tx: Option<oneshot::Sender<()>>,
-    pub root_path: PathBuf,
-    pub zip_progress: HashMap<String, ZipProgress>,
+    pub config: Config,
+    pub started_at: Instant,
+    pub last_request_at: AtomicU64,
+    pub shutdown_tx: Mutex<Option<oneshot::Sender<()>>>,
+    pub zip_progress: RwLock<HashMap<String, ZipProgress>>,
+    pub cancel_flags: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    // One permit per archive allowed to build at once (--max-concurrent-zips)
+    pub zip_slots: Option<Arc<Semaphore>>,
+    pub progress_events: broadcast::Sender<ProgressEvent>,
+    // None under --no-cache
+    pub listing_cache: Option<Mutex<ListingCache>>,
+    // None when --request-limit is 0
+    pub request_limiter: Option<IpRateLimiter>,
+    // Opened by the caller, since failing to open it is fatal there
+    pub access_log: Mutex<Option<Arc<AccessLog>>>,
+    pub share_tokens: ShareTokens,
+    // Folders that changed on disk under --watch, relative to the root
+    pub change_events: broadcast::Sender<Vec<String>>,
+    // Starts as the config's copy; --watch reloads it when it changes
+    pub ignore_file: RwLock<IgnoreFile>,
 }
 
+// How many progress events a slow subscriber may fall behind by
+const PROGRESS_EVENT_CAPACITY: usize = 1024;
+
+// Change batches are already debounced, so few are ever queued
+const CHANGE_EVENT_CAPACITY: usize = 64;
+
+// Progress changes pushed to /api/zip/events subscribers
+#[derive(Clone)]
+pub enum ProgressEvent {
+    Updated(String, ZipProgress),
+    Removed(String),
+}
+
+// Returned when --max-concurrent-zips archives are already being built
+#[derive(Debug)]
+pub struct ZipSlotsBusy;
+
 #[derive(Clone)]
 pub struct ServerState {
-    inner: Arc<Mutex<ServerStateInner>>,
+    inner: Arc<ServerStateInner>,
 }
 
 impl ServerState {
-    pub fn new(root_path: PathBuf) -> Self {
+    pub fn new(config: Config) -> Self {
+        let zip_slots = config.max_concurrent_zips.map(|n| Arc::new(Semaphore::new(n)));
+        let listing_cache = (!config.no_cache).then(|| Mutex::new(ListingCache::new()));
+        let request_limiter = config.request_limit.map(IpRateLimiter::new);
+        let ignore_file = RwLock::new(config.ignore_file.clone());
         Self {
-            inner: Arc::new(Mutex::new(ServerStateInner {
-                shutdown_tx: None,
-                root_path,
-                zip_progress: HashMap::new(),
-            })),
+            inner: Arc::new(ServerStateInner {
+                config,
+                started_at: Instant::now(),
+                last_request_at: AtomicU64::new(now_millis()),
+                shutdown_tx: Mutex::new(None),
+                zip_progress: RwLock::new(HashMap::new()),
+                cancel_flags: Mutex::new(HashMap::new()),
+                zip_slots,
+                progress_events: broadcast::channel(PROGRESS_EVENT_CAPACITY).0,
+                listing_cache,
+                request_limiter,
+                access_log: Mutex::new(None),
+                share_tokens: ShareTokens::new(),
+                change_events: broadcast::channel(CHANGE_EVENT_CAPACITY).0,
+                ignore_file,
+            }),
         }
     }
 
     pub fn set_shutdown_tx(&self, tx: oneshot::Sender<()>) {
-        let mut state = self.inner.lock().unwrap();
-        state.shutdown_tx = Some(tx);
+        let mut shutdown_tx = self.inner.shutdown_tx.lock().unwrap();
+        *shutdown_tx = Some(tx);
+    }
+
+    pub fn set_access_log(&self, access_log: Option<Arc<AccessLog>>) {
+        *self.inner.access_log.lock().unwrap() = access_log;
     }
 
-    pub fn update_progress(&self, operation_id: &str, progress: ZipProgress) {
-        let mut state = self.inner.lock().unwrap();
-        state.zip_progress.insert(operation_id.to_string(), progress);
+    pub fn share_tokens(&self) -> &ShareTokens {
+        &self.inner.share_tokens
+    }
+
+    pub fn access_log(&self) -> Option<Arc<AccessLog>> {
+        self.inner.access_log.lock().unwrap().clone()
+    }
+
+    pub fn update_progress(&self, operation_id: &str, mut progress: ZipProgress) {
+        progress.updated_at = now_millis();
+        let mut zip_progress = self.inner.zip_progress.write().unwrap();
+        zip_progress.insert(operation_id.to_string(), progress.clone());
+        // Sent under the lock so subscribers see updates in order; having
+        // no subscribers isn't an error
+        let _ = self.inner.progress_events.send(ProgressEvent::Updated(operation_id.to_string(), progress));
     }
 
     pub fn get_progress(&self, operation_id: &str) -> Option<ZipProgress> {
-        let state = self.inner.lock().unwrap();
-        state.zip_progress.get(operation_id).cloned()
+        let zip_progress = self.inner.zip_progress.read().unwrap();
+        zip_progress.get(operation_id).cloned()
     }
 
     pub fn remove_progress(&self, operation_id: &str) {
-        let mut state = self.inner.lock().unwrap();
-        state.zip_progress.remove(operation_id);
+        let mut zip_progress = self.inner.zip_progress.write().unwrap();
+        if zip_progress.remove(operation_id).is_some() {
+            let _ = self.inner.progress_events.send(ProgressEvent::Removed(operation_id.to_string()));
+        }
+    }
+
+    // Tell /api/watch subscribers which folders changed, after dropping
+    // their cached listings
+    pub fn notify_changes(&self, dirs: Vec<String>) {
+        for dir in &dirs {
+            if let Some(path) = self.resolve_path(dir) {
+                self.invalidate_listing(&path);
+            }
+        }
+        let _ = self.inner.change_events.send(dirs);
+    }
+
+    // Swap in a re-read .servefolderignore. Every cached listing was
+    // filtered by the old one, so they all go
+    pub fn set_ignore_file(&self, ignore_file: IgnoreFile) {
+        *self.inner.ignore_file.write().unwrap() = ignore_file;
+        if let Some(cache) = &self.inner.listing_cache {
+            cache.lock().unwrap().clear();
+        }
+    }
+
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<Vec<String>> {
+        self.inner.change_events.subscribe()
+    }
+
+    // Receive every progress update and removal from now on
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<ProgressEvent> {
+        self.inner.progress_events.subscribe()
+    }
+
+    // Get (or create) the cancellation flag for an operation
+    pub fn cancel_flag(&self, operation_id: &str) -> Arc<AtomicBool> {
+        let mut cancel_flags = self.inner.cancel_flags.lock().unwrap();
+        cancel_flags
+            .entry(operation_id.to_string())
+            .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+            .clone()
+    }
+
+    // Request cancellation; returns false if the operation is unknown
+    pub fn cancel_operation(&self, operation_id: &str) -> bool {
+        let zip_progress = self.inner.zip_progress.read().unwrap();
+        let mut cancel_flags = self.inner.cancel_flags.lock().unwrap();
+        if !zip_progress.contains_key(operation_id) && !cancel_flags.contains_key(operation_id) {
+            return false;
+        }
+        cancel_flags
+            .entry(operation_id.to_string())
+            .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+            .store(true, Ordering::Relaxed);
+        true
+    }
+
+    pub fn remove_cancel_flag(&self, operation_id: &str) {
+        let mut cancel_flags = self.inner.cancel_flags.lock().unwrap();
+        cancel_flags.remove(operation_id);
+    }
+
+    // Claim a slot for building an archive, held until the permit drops.
+    // Err means every slot is taken; without a limit there's nothing to hold
+    pub fn try_acquire_zip_slot(&self) -> Result<Option<OwnedSemaphorePermit>, ZipSlotsBusy> {
+        match &self.inner.zip_slots {
+            Some(slots) => slots.clone().try_acquire_owned().map(Some).map_err(|_| ZipSlotsBusy),
+            None => Ok(None),
+        }
+    }
+
+    // Drop progress entries (and their cancel flags) not updated within max_age
+    pub fn evict_stale_progress(&self, max_age: Duration) -> usize {
+        let cutoff = now_millis().saturating_sub(max_age.as_millis() as u64);
+        let mut zip_progress = self.inner.zip_progress.write().unwrap();
+        let mut cancel_flags = self.inner.cancel_flags.lock().unwrap();
+        let stale: Vec<String> = zip_progress.iter()
+            .filter(|(_, progress)| progress.updated_at < cutoff)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in &stale {
+            zip_progress.remove(id);
+            cancel_flags.remove(id);
+            let _ = self.inner.progress_events.send(ProgressEvent::Removed(id.clone()));
+        }
+        stale.len()
     }
 
     pub fn with_state(&self) -> impl Filter<Extract = (ServerState,), Error = std::convert::Infallible> + Clone {
@@ -53,13 +220,301 @@ impl ServerState {
         warp::any().map(move || state.clone())
     }
 
+    // Filter that records every request for --idle-timeout
+    pub fn track_activity(&self) -> impl Filter<Extract = (), Error = std::convert::Infallible> + Clone {
+        let state = self.clone();
+        warp::any()
+            .map(move || state.inner.last_request_at.store(now_millis(), Ordering::Relaxed))
+            .untuple_one()
+    }
+
+    // Filter for the expensive endpoints that rejects clients over
+    // --request-limit. Requests with no known client address are let
+    // through, as there's nothing to key them on
+    pub fn limit_requests(&self) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+        let state = self.clone();
+        with_client_ip(self.config().trust_proxy)
+            .and_then(move |client: Option<IpAddr>| {
+                let state = state.clone();
+                async move {
+                    match (&state.inner.request_limiter, client) {
+                        (Some(limiter), Some(ip)) => limiter.check(ip).map_err(|retry_after| {
+                            warp::reject::custom(RateLimited {
+                                retry_after_secs: retry_after.as_secs_f64().ceil() as u64,
+                            })
+                        }),
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .untuple_one()
+    }
+
+    // Time since the last request was received
+    pub fn idle_for(&self) -> Duration {
+        let last = self.inner.last_request_at.load(Ordering::Relaxed);
+        Duration::from_millis(now_millis().saturating_sub(last))
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
+    // Resolve a client path under the root, refusing excluded paths and
+    // symlink escapes unless --follow-symlinks was given
+    pub fn resolve_path(&self, rel: &str) -> Option<PathBuf> {
+        let path = resolve_safe_path(&self.inner.config.root_path, rel)?;
+        if !self.is_path_allowed(&path) || self.is_excluded(&path) {
+            return None;
+        }
+        Some(path)
+    }
+
+    // Whether a path under the root may be served, listed or archived
+    pub fn is_path_allowed(&self, path: &Path) -> bool {
+        let config = &self.inner.config;
+        config.follow_symlinks || is_within_root(&config.canonical_root, path)
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.inner.started_at.elapsed()
+    }
+
+    // Whether a path under the root is hidden from listings, archives and
+    // downloads: by its name, or by being a file over --max-file-size
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.is_excluded_by_name(path) || self.is_too_large(path)
+    }
+
+    // Whether a path under the root is a dotfile (unless --show-hidden),
+    // matches an --exclude pattern or is in .servefolderignore. The root's
+    // serve-folder.json is always hidden: it's read as options at startup,
+    // so visitors mustn't read it or upload one
+    fn is_excluded_by_name(&self, path: &Path) -> bool {
+        let config = &self.inner.config;
+        let rel_path = path.strip_prefix(&config.root_path).unwrap_or(path);
+        // Only the chosen file is visible, even if it's a dotfile
+        if let Some(single_file) = &config.single_file {
+            return !rel_path.as_os_str().is_empty() && rel_path != single_file;
+        }
+        is_root_config_file(rel_path)
+            || (!config.show_hidden && is_hidden(rel_path))
+            || config.excludes.is_excluded(rel_path)
+            || self.inner.ignore_file.read().unwrap().is_ignored(rel_path, || path.is_dir())
+    }
+
+    // Whether a path is a file bigger than --max-file-size. Costs a stat,
+    // so only when the option is set
+    pub fn is_too_large(&self, path: &Path) -> bool {
+        match self.inner.config.max_file_size {
+            Some(max_file_size) => fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() > max_file_size),
+            None => false,
+        }
+    }
+
+    // Whether resolve_path refused `rel` only because of --max-file-size,
+    // so the refusal can say so without revealing anything else
+    pub fn refused_for_size(&self, rel: &str) -> bool {
+        resolve_safe_path(&self.inner.config.root_path, rel).is_some_and(|path| {
+            self.is_path_allowed(&path) && !self.is_excluded_by_name(&path) && self.is_too_large(&path)
+        })
+    }
+
     pub fn get_root_path(&self) -> PathBuf {
-        let state = self.inner.lock().unwrap();
-        state.root_path.clone()
+        self.inner.config.root_path.clone()
+    }
+
+    pub fn get_compression(&self) -> CompressionLevel {
+        self.inner.config.compression
+    }
+
+    // A directory's entries as last read, if its mtime still matches
+    pub fn cached_listing(&self, dir: &Path, mtime: SystemTime) -> Option<Vec<FileEntry>> {
+        self.inner.listing_cache.as_ref()?.lock().unwrap().get(dir, mtime)
+    }
+
+    pub fn cache_listing(&self, dir: &Path, mtime: SystemTime, entries: &[FileEntry]) {
+        if let Some(cache) = &self.inner.listing_cache {
+            cache.lock().unwrap().insert(dir, mtime, entries);
+        }
+    }
+
+    // Forget a directory's listing after changing its contents, for the
+    // changes its mtime doesn't reflect (a file growing as it's written)
+    pub fn invalidate_listing(&self, dir: &Path) {
+        if let Some(cache) = &self.inner.listing_cache {
+            cache.lock().unwrap().invalidate(dir);
+        }
+    }
+
+    // Whether /api/stop can still shut the server down
+    pub fn can_stop(&self) -> bool {
+        self.inner.shutdown_tx.lock().unwrap().is_some()
     }
 
     pub fn take_shutdown_tx(&self) -> Option<oneshot::Sender<()>> {
-        let mut state = self.inner.lock().unwrap();
-        state.shutdown_tx.take()
+        let mut shutdown_tx = self.inner.shutdown_tx.lock().unwrap();
+        shutdown_tx.take()
+    }
+}
+
+// Releases an archive operation when dropped: asks any work still running
+// for it to stop and removes its progress entry and cancel flag. The
+// download handler holds one until the response body is finished, so a
+// client that disconnects at any point leaves nothing behind.
+pub struct OperationGuard {
+    state: ServerState,
+    operation_id: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl OperationGuard {
+    pub fn new(state: &ServerState, operation_id: &str) -> Self {
+        Self {
+            state: state.clone(),
+            operation_id: operation_id.to_string(),
+            cancelled: state.cancel_flag(operation_id),
+        }
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.state.remove_progress(&self.operation_id);
+        self.state.remove_cancel_flag(&self.operation_id);
+    }
+}
+
+// Case doesn't matter, since on Windows and macOS any casing is that file
+fn is_root_config_file(rel_path: &Path) -> bool {
+    rel_path.to_str().is_some_and(|rel_path| rel_path.eq_ignore_ascii_case(CONFIG_FILE_NAME))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn state_for(root: &Path, show_hidden: bool) -> ServerState {
+        ServerState::new(Config {
+            root_path: root.to_path_buf(),
+            canonical_root: fs::canonicalize(root).unwrap(),
+            show_hidden,
+            ..Config::default()
+        })
+    }
+
+    fn served_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("visible.txt"), "visible").unwrap();
+        fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git").join("config"), "[core]").unwrap();
+        dir
+    }
+
+    #[test]
+    fn hidden_files_are_refused_by_default() {
+        let dir = served_dir();
+        let state = state_for(dir.path(), false);
+
+        assert!(state.resolve_path("visible.txt").is_some());
+        assert!(state.resolve_path(".env").is_none());
+        assert!(state.resolve_path(".git/config").is_none());
+        assert!(state.resolve_path("").is_some());
+    }
+
+    #[test]
+    fn show_hidden_allows_dotfiles() {
+        let dir = served_dir();
+        let state = state_for(dir.path(), true);
+
+        assert!(state.resolve_path(".env").is_some());
+        assert!(state.resolve_path(".git/config").is_some());
+        assert!(!state.is_excluded(&dir.path().join(".git")));
+    }
+
+    #[test]
+    fn max_file_size_keeps_files_up_to_the_limit() {
+        let dir = served_dir();
+        fs::write(dir.path().join("exact.bin"), [0; 10]).unwrap();
+        fs::write(dir.path().join("over.bin"), [0; 11]).unwrap();
+        fs::write(dir.path().join(".big"), [0; 11]).unwrap();
+        let state = ServerState::new(Config {
+            root_path: dir.path().to_path_buf(),
+            canonical_root: fs::canonicalize(dir.path()).unwrap(),
+            max_file_size: Some(10),
+            ..Config::default()
+        });
+
+        assert!(state.resolve_path("exact.bin").is_some());
+        assert!(state.resolve_path("over.bin").is_none());
+        assert!(state.refused_for_size("over.bin"));
+        // Hidden anyway, so its size isn't what to report
+        assert!(!state.refused_for_size(".big"));
+        // Directories have no size limit
+        assert!(state.resolve_path("").is_some());
+    }
+
+    #[test]
+    fn ignore_file_adds_to_excludes() {
+        let dir = served_dir();
+        fs::create_dir(dir.path().join("build")).unwrap();
+        fs::write(dir.path().join("build").join("out.o"), "o").unwrap();
+        fs::write(dir.path().join("debug.log"), "log").unwrap();
+        fs::write(dir.path().join(".servefolderignore"), "build/\n*.log\n!.env\n").unwrap();
+        let state = ServerState::new(Config {
+            root_path: dir.path().to_path_buf(),
+            canonical_root: fs::canonicalize(dir.path()).unwrap(),
+            ignore_file: IgnoreFile::load(dir.path()).unwrap(),
+            ..Config::default()
+        });
+
+        assert!(state.resolve_path("build/out.o").is_none());
+        assert!(state.resolve_path("debug.log").is_none());
+        assert!(state.resolve_path("visible.txt").is_some());
+        // Re-including can't undo hiding dotfiles
+        assert!(state.resolve_path(".env").is_none());
+
+        state.set_ignore_file(IgnoreFile::default());
+        assert!(state.resolve_path("debug.log").is_some());
+    }
+
+    #[test]
+    fn listing_skips_hidden_entries() {
+        let dir = served_dir();
+        let state = state_for(dir.path(), false);
+
+        let mut visible: Vec<String> = fs::read_dir(dir.path()).unwrap()
+            .flatten()
+            .filter(|entry| !state.is_excluded(&entry.path()))
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        visible.sort();
+        assert_eq!(visible, vec!["visible.txt"]);
+    }
+
+    #[test]
+    fn single_file_hides_its_siblings() {
+        let dir = served_dir();
+        let state = ServerState::new(Config {
+            root_path: dir.path().to_path_buf(),
+            canonical_root: fs::canonicalize(dir.path()).unwrap(),
+            single_file: Some(PathBuf::from(".env")),
+            ..Config::default()
+        });
+
+        assert!(state.resolve_path(".env").is_some());
+        assert!(state.resolve_path("visible.txt").is_none());
+        assert!(state.resolve_path(".git/config").is_none());
+        assert!(state.resolve_path("").is_some());
     }
 }
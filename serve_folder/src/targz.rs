@@ -0,0 +1,141 @@
+use std::path::Path;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use walkdir::WalkDir;
+
+use crate::state::ServerState;
+use crate::models::ZipProgress;
+use crate::zip::{archive_totals, eta_secs, with_context, CompressionLevel, DirectoryTotals, ThroughputMeter};
+
+// Create a gzip-compressed tarball, reporting progress like the ZIP path
+pub async fn create_tar_gz_archive(
+    root_dir: impl AsRef<Path>,
+    base_dir: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    operation_id: String,
+    compression: CompressionLevel,
+    state: ServerState,
+) -> io::Result<()> {
+    // Convert to owned values that can be moved into the closure
+    let root_dir = root_dir.as_ref().to_path_buf();
+    let base_dir = base_dir.as_ref().to_path_buf();
+    let output_path = output_path.as_ref().to_path_buf();
+    
+    // Taken before the blocking task starts, as in create_zip_archive
+    let cancelled = state.cancel_flag(&operation_id);
+    
+    tokio::task::spawn_blocking(move || {
+        // Get totals first
+        let totals = archive_totals(&state, &operation_id, &base_dir);
+        
+        state.update_progress(&operation_id, ZipProgress {
+            current_file: format!("Initializing tar.gz compression ({})...", compression.name()),
+            processed_files: 0,
+            total_files: totals.files,
+            total_bytes: totals.bytes,
+            percentage: 0.0,
+            ..Default::default()
+        });
+        
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "ZIP operation cancelled"));
+        }
+        
+        // Tar keeps symlinks as links and records real permissions
+        let file = BufWriter::new(fs::File::create(&output_path).map_err(|e| with_context(e, "Cannot create archive file"))?);
+        let encoder = GzEncoder::new(file, gzip_compression(compression));
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(state.config().follow_symlinks);
+        
+        let update_interval = Duration::from_millis(100);
+        let mut last_update = Instant::now();
+        let mut processed = 0;
+        let mut processed_bytes = 0;
+        let mut throughput = ThroughputMeter::new();
+        throughput.sample(0);
+        
+        let walker = WalkDir::new(&base_dir)
+            .sort_by_file_name()
+            .min_depth(1)
+            .follow_links(state.config().follow_symlinks);
+        for entry in walker.into_iter().filter_entry(|e| !state.is_excluded(e.path())).filter_map(|e| e.ok()) {
+            if cancelled.load(Ordering::Relaxed) {
+                // Drop any progress published after the requester cleaned up
+                state.remove_progress(&operation_id);
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "ZIP operation cancelled"));
+            }
+            
+            // Leave out symlinks that lead outside the served root
+            if entry.path_is_symlink() && !state.is_path_allowed(entry.path()) {
+                continue;
+            }
+            
+            let rel_path = entry.path().strip_prefix(&root_dir).unwrap_or(entry.path());
+            builder.append_path_with_name(entry.path(), rel_path)
+                .map_err(|e| with_context(e, &format!("Cannot add {}", rel_path.display())))?;
+            
+            if !entry.file_type().is_dir() {
+                processed += 1;
+                processed_bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+            
+            // Throttle progress updates to the same cadence as the ZIP tracker
+            if last_update.elapsed() >= update_interval {
+                let bytes_per_sec = throughput.sample(processed_bytes);
+                report_progress(&state, &operation_id, rel_path, processed, processed_bytes, bytes_per_sec, totals);
+                last_update = Instant::now();
+            }
+        }
+        
+        builder.into_inner()?.finish()?;
+        
+        // Final update
+        state.update_progress(&operation_id, ZipProgress {
+            current_file: "tar.gz archive complete".to_string(),
+            processed_files: totals.files,
+            total_files: totals.files,
+            processed_bytes: totals.bytes,
+            total_bytes: totals.bytes,
+            percentage: 100.0,
+            ..Default::default()
+        });
+        
+        Ok(())
+    }).await?
+}
+
+// Map the shared compression preset onto a gzip level
+fn gzip_compression(level: CompressionLevel) -> Compression {
+    match level {
+        CompressionLevel::Store => Compression::none(),
+        CompressionLevel::Fast => Compression::fast(),
+        CompressionLevel::Default => Compression::default(),
+        CompressionLevel::Best => Compression::best(),
+    }
+}
+
+fn report_progress(
+    state: &ServerState,
+    operation_id: &str,
+    current: &Path,
+    processed: usize,
+    processed_bytes: u64,
+    bytes_per_sec: f64,
+    totals: DirectoryTotals,
+) {
+    state.update_progress(operation_id, ZipProgress {
+        current_file: current.to_string_lossy().to_string(),
+        processed_files: processed,
+        total_files: totals.files,
+        processed_bytes,
+        total_bytes: totals.bytes,
+        percentage: totals.percentage(processed, processed_bytes),
+        bytes_per_sec: bytes_per_sec as u64,
+        eta_secs: eta_secs(bytes_per_sec, totals.bytes.saturating_sub(processed_bytes)),
+        ..Default::default()
+    });
+}
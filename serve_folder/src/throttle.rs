@@ -0,0 +1,86 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures_util::StreamExt;
+use warp::http::header::CONTENT_TYPE;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+// Token bucket shared by every response, so --rate-limit caps the
+// server's total egress rather than each connection's
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    // Bytes that may be sent right now; negative while in debt
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+    
+    // Take `bytes` from the bucket, waiting until they're paid for. Chunks
+    // larger than the bucket are allowed through by going into debt, which
+    // later senders then wait out
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.bytes_per_sec;
+            // At most one second's worth of burst
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec);
+            bucket.refilled_at = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// Pace the body of file and archive responses through the limiter. JSON
+// API replies and progress events are left alone so the web UI stays
+// responsive while a big download is using up the allowance
+pub fn throttle_reply(response: Response, limiter: Option<Arc<RateLimiter>>) -> Response {
+    let limiter = match limiter {
+        Some(limiter) if response.status().is_success() && !is_api_reply(&response) => limiter,
+        _ => return response,
+    };
+    
+    let (parts, body) = response.into_parts();
+    let body = body.then(move |chunk| {
+        let limiter = limiter.clone();
+        async move {
+            if let Ok(bytes) = &chunk {
+                limiter.consume(bytes.len()).await;
+            }
+            chunk
+        }
+    });
+    Response::from_parts(parts, Body::wrap_stream(body))
+}
+
+fn is_api_reply(response: &Response) -> bool {
+    response.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|content_type| {
+            content_type.starts_with("application/json") || content_type.starts_with("text/event-stream")
+        })
+        .unwrap_or(false)
+}
@@ -0,0 +1,105 @@
+use std::fs::{self, Metadata};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageReader, Limits};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+// Edge length used when the client doesn't pass a size, and the largest
+// one it may ask for
+pub const DEFAULT_THUMB_SIZE: u32 = 200;
+pub const MAX_THUMB_SIZE: u32 = 1024;
+
+// Sources bigger than this are refused before decoding; a 100-megapixel
+// RGBA image already needs 400 MB
+const MAX_SOURCE_PIXELS: u64 = 100_000_000;
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+const JPEG_QUALITY: u8 = 80;
+
+#[derive(Debug)]
+pub enum ThumbnailError {
+    // Not an image we can decode
+    Unsupported,
+    TooLarge,
+    Io(io::Error),
+}
+
+impl From<io::Error> for ThumbnailError {
+    fn from(e: io::Error) -> Self {
+        ThumbnailError::Io(e)
+    }
+}
+
+// Formats the image crate is built with here; SVG, ICO and AVIF aren't
+pub fn is_thumbnailable(path: &Path) -> bool {
+    let extension = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return false,
+    };
+    matches!(extension.as_str(), "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp")
+}
+
+// Path of a JPEG thumbnail of `source` fitting in `size`×`size`, made on
+// first request and then reused until the source changes
+pub fn thumbnail(source: &Path, metadata: &Metadata, size: u32) -> Result<PathBuf, ThumbnailError> {
+    let cache_dir = std::env::temp_dir().join("serve-folder-thumbnails");
+    let cached = cache_dir.join(format!("{}.jpg", cache_key(source, metadata, size)));
+    if cached.is_file() {
+        return Ok(cached);
+    }
+    
+    let mut decoder = ImageReader::open(source)?
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(decode_error)?;
+    let (width, height) = decoder.dimensions();
+    if u64::from(width) * u64::from(height) > MAX_SOURCE_PIXELS {
+        return Err(ThumbnailError::TooLarge);
+    }
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    decoder.set_limits(limits).map_err(decode_error)?;
+    let image = DynamicImage::from_decoder(decoder).map_err(decode_error)?;
+    
+    // JPEG has no alpha channel, so flatten to RGB
+    let resized = image.thumbnail(size, size).to_rgb8();
+    
+    // Write next to the final name and rename, so concurrent requests
+    // never serve a half-written file
+    fs::create_dir_all(&cache_dir)?;
+    let temp = NamedTempFile::new_in(&cache_dir)?;
+    let mut writer = BufWriter::new(temp.as_file());
+    JpegEncoder::new_with_quality(&mut writer, JPEG_QUALITY)
+        .encode_image(&resized)
+        .map_err(|_| io::Error::other("Failed to encode thumbnail"))?;
+    drop(writer);
+    temp.persist(&cached).map_err(|e| e.error)?;
+    Ok(cached)
+}
+
+fn decode_error(e: ImageError) -> ThumbnailError {
+    match e {
+        ImageError::IoError(e) => ThumbnailError::Io(e),
+        ImageError::Limits(_) => ThumbnailError::TooLarge,
+        _ => ThumbnailError::Unsupported,
+    }
+}
+
+// Path, modification time, length and requested size, so an edited file
+// gets a fresh thumbnail
+fn cache_key(source: &Path, metadata: &Metadata, size: u32) -> String {
+    let mtime = metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    
+    let mut hasher = Sha256::new();
+    hasher.update(source.to_string_lossy().as_bytes());
+    hasher.update(mtime.to_le_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(size.to_le_bytes());
+    hex::encode(hasher.finalize())
+}
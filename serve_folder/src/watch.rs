@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::ignore_file::{IgnoreFile, IGNORE_FILE_NAME};
+use crate::paths::encode_rel_path;
+use crate::state::ServerState;
+
+// Events closer together than this are sent as one batch
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+// A steady stream of changes still gets a batch out this often
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(1);
+
+// Watch the served folder under --watch, passing debounced batches of
+// changed folders to the state. Watching stops when the watcher is dropped
+pub fn watch_root(state: ServerState) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            let _ = tx.send(event);
+        }
+    })?;
+    watcher.watch(&state.config().canonical_root, RecursiveMode::Recursive)?;
+    
+    std::thread::spawn(move || forward_changes(rx, state));
+    Ok(watcher)
+}
+
+// Runs until the watcher, and with it the sender, is dropped
+fn forward_changes(rx: Receiver<Event>, state: ServerState) {
+    let ignore_path = state.config().canonical_root.join(IGNORE_FILE_NAME);
+    while let Ok(first) = rx.recv() {
+        let started = Instant::now();
+        let mut dirs = BTreeSet::new();
+        let mut ignore_changed = first.paths.contains(&ignore_path);
+        add_changed_dirs(&first, &state, &mut dirs);
+        
+        // Keep collecting until things go quiet, or the batch is overdue
+        loop {
+            let wait = DEBOUNCE.min(MAX_BATCH_DELAY.saturating_sub(started.elapsed()));
+            match rx.recv_timeout(wait) {
+                Ok(event) => {
+                    ignore_changed |= event.paths.contains(&ignore_path);
+                    add_changed_dirs(&event, &state, &mut dirs);
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        
+        if ignore_changed && state.config().single_file.is_none() {
+            reload_ignore_file(&state);
+            dirs.insert(String::new());
+        }
+        
+        if !dirs.is_empty() {
+            state.notify_changes(dirs.into_iter().collect());
+        }
+    }
+}
+
+// Re-read .servefolderignore after an edit. A broken one is reported and
+// the old patterns stay, rather than exposing what they hid
+fn reload_ignore_file(state: &ServerState) {
+    match IgnoreFile::load(&state.config().root_path) {
+        Ok(ignore_file) => state.set_ignore_file(ignore_file),
+        Err(e) => eprintln!("{}; keeping the previous patterns", e),
+    }
+}
+
+// A file changing changes the listing of the folder it's in. Excluded and
+// hidden paths are left out, so watching doesn't reveal them
+fn add_changed_dirs(event: &Event, state: &ServerState, dirs: &mut BTreeSet<String>) {
+    let config = state.config();
+    for path in &event.paths {
+        let rel_path = match path.strip_prefix(&config.canonical_root) {
+            Ok(rel_path) if !rel_path.as_os_str().is_empty() => rel_path,
+            _ => continue,
+        };
+        if state.is_excluded(&config.root_path.join(rel_path)) {
+            continue;
+        }
+        dirs.insert(encode_rel_path(rel_path.parent().unwrap_or(Path::new(""))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::config::Config;
+    
+    #[tokio::test]
+    async fn changes_arrive_as_their_folders() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("sub")).unwrap();
+        let state = ServerState::new(Config {
+            root_path: root.path().to_path_buf(),
+            canonical_root: fs::canonicalize(root.path()).unwrap(),
+            ..Config::default()
+        });
+        let mut changes = state.subscribe_changes();
+        let _watcher = watch_root(state.clone()).unwrap();
+        
+        fs::write(root.path().join("sub").join("a.txt"), "a").unwrap();
+        fs::write(root.path().join(".hidden"), "h").unwrap();
+        
+        let dirs = tokio::time::timeout(Duration::from_secs(5), changes.recv()).await.unwrap().unwrap();
+        assert_eq!(dirs, ["sub"]);
+    }
+    
+    #[tokio::test]
+    async fn ignore_file_edits_apply_right_away() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("notes.txt"), "n").unwrap();
+        let state = ServerState::new(Config {
+            root_path: root.path().to_path_buf(),
+            canonical_root: fs::canonicalize(root.path()).unwrap(),
+            ..Config::default()
+        });
+        let mut changes = state.subscribe_changes();
+        let _watcher = watch_root(state.clone()).unwrap();
+        assert!(state.resolve_path("notes.txt").is_some());
+        
+        fs::write(root.path().join(IGNORE_FILE_NAME), "*.txt\n").unwrap();
+        
+        let dirs = tokio::time::timeout(Duration::from_secs(5), changes.recv()).await.unwrap().unwrap();
+        assert_eq!(dirs, [""]);
+        assert!(state.resolve_path("notes.txt").is_none());
+    }
+}
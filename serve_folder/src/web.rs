@@ -1,18 +1,211 @@
-use warp::{Reply, Rejection};
+use std::borrow::Cow;
+use std::path::Path;
+use include_dir::{include_dir, Dir, File};
+use warp::Rejection;
+use warp::http::{HeaderValue, header::CONTENT_TYPE};
+use warp::reply::Response;
+
+use crate::autoindex::escape_html;
+use crate::caching::{cache_forever, revalidate, Conditionals, Validators};
+use crate::mime::content_type_for;
+use crate::state::ServerState;
+
+// The whole web/ directory, built into the binary. Everything in it is
+// served as-is under /webui except the page templates. The pages link
+// assets with a content hash in the query string, so browsers may keep
+// them for good
+static WEB_DIR: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/web");
+
+// Filled in per request rather than served as they are
+const TEMPLATES: [&str; 2] = ["index.html", "404.html"];
+
+// Page shown for missing files outside the API, linking back to the web UI
+// under --base-path
+pub fn not_found_page(base_path: &str) -> String {
+    let html = include_str!("../web/404.html").replace("{base_path}", &escape_html(base_path));
+    with_asset_versions(&html)
+}
 
 // Serve embedded web UI files
-pub async fn serve_web_ui(path: warp::path::Tail) -> Result<impl Reply, Rejection> {
-    let path = path.as_str();
-    let content_type = match path {
-        "" | "index.html" => ("text/html", include_str!("../web/index.html")),
-        "style.css" => ("text/css", include_str!("../web/style.css")),
-        "script.js" => ("application/javascript", include_str!("../web/script.js")),
-        _ => return Err(warp::reject::not_found()),
+pub async fn serve_web_ui(path: warp::path::Tail, conditionals: Conditionals, state: ServerState) -> Result<Response, Rejection> {
+    let path = match path.as_str() {
+        "" => "index.html",
+        other => other,
+    };
+    let body: Cow<'static, [u8]> = if path == "index.html" {
+        let config = state.config();
+        let html = with_title(include_str!("../web/index.html"), config.title.as_deref());
+        let html = with_base_path(html, &config.base_path);
+        Cow::Owned(with_asset_versions(&html).into_bytes())
+    } else {
+        match asset(path) {
+            Some(body) => Cow::Borrowed(body),
+            None => return Err(warp::reject::not_found()),
+        }
+    };
+    
+    // Hashing the served body means a new --title gets a new ETag
+    let validators = Validators::for_embedded(&body);
+    let mut response = if validators.is_not_modified(&conditionals) {
+        validators.not_modified()
+    } else {
+        let mut response = Response::new(body.into_owned().into());
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type_for(Path::new(path))));
+        validators.apply(&mut response);
+        response
     };
+    // The page itself changes with --title and --base-path under the same URL
+    if path == "index.html" {
+        revalidate(&mut response);
+    } else {
+        cache_forever(&mut response);
+    }
+    
+    Ok(response)
+}
+
+// Point links to webui/<asset> at webui/<asset>?v=<hash of its content>,
+// so a new build's assets are fetched afresh despite cache_forever
+fn with_asset_versions(html: &str) -> String {
+    let mut files = Vec::new();
+    collect_files(&WEB_DIR, &mut files);
+    
+    let mut html = html.to_string();
+    for file in files {
+        let name = file.path().to_string_lossy();
+        if TEMPLATES.contains(&name.as_ref()) {
+            continue;
+        }
+        let version = Validators::for_embedded(file.contents()).etag;
+        html = html.replace(
+            &format!("webui/{}\"", name),
+            &format!("webui/{}?v={}\"", name, version.trim_matches('"')),
+        );
+    }
+    html
+}
+
+// Contents of the asset at `path` under web/, like "style.css" or
+// "icons/folder.svg"
+fn asset(path: &str) -> Option<&'static [u8]> {
+    if TEMPLATES.contains(&path) {
+        return None;
+    }
+    WEB_DIR.get_file(path).map(|file| file.contents())
+}
+
+fn collect_files(dir: &'static Dir<'static>, files: &mut Vec<&'static File<'static>>) {
+    files.extend(dir.files());
+    for subdir in dir.dirs() {
+        collect_files(subdir, files);
+    }
+}
+
+// Put the --title into the page title and heading
+fn with_title(html: &'static str, title: Option<&str>) -> Cow<'static, str> {
+    match title {
+        Some(title) => {
+            let title = escape_html(title);
+            Cow::Owned(html
+                .replace("<title>File Server</title>", &format!("<title>{}</title>", title))
+                .replace("<h1>File Server</h1>", &format!("<h1>{}</h1>", title)))
+        }
+        None => Cow::Borrowed(html),
+    }
+}
+
+// Tell the script which prefix to put on its requests
+fn with_base_path(html: Cow<'static, str>, base_path: &str) -> Cow<'static, str> {
+    if base_path.is_empty() {
+        return html;
+    }
+    let tag = format!("<html lang=\"en\" data-base-path=\"{}\">", escape_html(base_path));
+    Cow::Owned(html.replace("<html lang=\"en\">", &tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+    use warp::http::StatusCode;
+    use crate::caching::conditionals;
+    use crate::config::Config;
+    
+    fn web_ui_with(config: Config) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+        let state = ServerState::new(config);
+        warp::path("webui")
+            .and(warp::path::tail())
+            .and(conditionals())
+            .and(state.with_state())
+            .and_then(serve_web_ui)
+    }
+    
+    fn web_ui() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+        web_ui_with(Config::default())
+    }
+    
+    #[tokio::test]
+    async fn etag_round_trip_returns_304() {
+        let first = warp::test::request().path("/webui/style.css").reply(&web_ui()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()["etag"].to_str().unwrap().to_string();
+        
+        let second = warp::test::request()
+            .path("/webui/style.css")
+            .header("if-none-match", &etag)
+            .reply(&web_ui())
+            .await;
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert!(second.body().is_empty());
+        assert_eq!(second.headers()["etag"], etag.as_str());
+    }
+    
+    #[tokio::test]
+    async fn stale_etag_gets_full_body() {
+        let response = warp::test::request()
+            .path("/webui/script.js")
+            .header("if-none-match", "\"stale\"")
+            .reply(&web_ui())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.body().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn favicon_is_served_and_cached() {
+        let response = warp::test::request().path("/webui/favicon.ico").reply(&web_ui()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/x-icon");
+        assert!(response.headers()["cache-control"].to_str().unwrap().contains("immutable"));
+    }
+    
+    #[tokio::test]
+    async fn unknown_assets_and_templates_are_not_found() {
+        for path in ["/webui/missing.js", "/webui/404.html", "/webui/../Cargo.toml"] {
+            let response = warp::test::request().path(path).reply(&web_ui()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+    
+    #[tokio::test]
+    async fn page_links_versioned_assets() {
+        let response = warp::test::request().path("/webui/").reply(&web_ui()).await;
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        let version = Validators::for_embedded(include_bytes!("../web/style.css")).etag;
+        assert!(body.contains(&format!("href=\"webui/style.css?v={}\"", version.trim_matches('"'))));
+        assert!(body.contains("src=\"webui/script.js?v="));
+    }
     
-    Ok(warp::reply::with_header(
-        content_type.1,
-        "content-type",
-        content_type.0,
-    ))
+    #[tokio::test]
+    async fn title_is_escaped_into_the_page() {
+        let config = Config {
+            title: Some("Q3 <Reports> & Co".to_string()),
+            ..Config::default()
+        };
+        let response = warp::test::request().path("/webui/").reply(&web_ui_with(config)).await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("<title>Q3 &lt;Reports&gt; &amp; Co</title>"));
+        assert!(body.contains("<h1>Q3 &lt;Reports&gt; &amp; Co</h1>"));
+    }
 }
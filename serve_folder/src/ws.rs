@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use warp::ws::{Message, WebSocket};
+
+use crate::models::WsCommand;
+use crate::state::{ProgressEvent, ServerState};
+
+// One /api/ws connection. The client sends JSON commands:
+//   {"type": "subscribe", "id": "<operation>"}  follow an operation
+//   {"type": "cancel", "id": "<operation>"}     cancel it
+// and receives {"type": "progress", "id", "progress"} for each update of a
+// subscribed operation, {"type": "done", "id"} once it's gone, and
+// {"type": "cancelled", "id", "success"} in answer to a cancel.
+// Everything runs in this one task, which ends when the socket closes
+pub async fn handle_ws_connection(socket: WebSocket, state: ServerState) {
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut events = state.subscribe_progress();
+    let mut subscribed = HashSet::new();
+    
+    loop {
+        let reply = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(message)) if message.is_close() => break,
+                Some(Ok(message)) => match message.to_str() {
+                    Ok(text) => handle_command(text, &state, &mut subscribed),
+                    // Pings are answered by the library; binary is ignored
+                    Err(()) => None,
+                },
+                // Disconnected or broken connection
+                _ => break,
+            },
+            event = events.recv() => match event {
+                Ok(ProgressEvent::Updated(id, progress)) if subscribed.contains(&id) => {
+                    Some(serde_json::json!({ "type": "progress", "id": id, "progress": progress }))
+                }
+                Ok(ProgressEvent::Removed(id)) if subscribed.remove(&id) => {
+                    Some(serde_json::json!({ "type": "done", "id": id }))
+                }
+                // Missed updates are superseded by the next one
+                Ok(_) | Err(RecvError::Lagged(_)) => None,
+                Err(RecvError::Closed) => break,
+            },
+        };
+        
+        if let Some(reply) = reply {
+            if outgoing.send(Message::text(reply.to_string())).await.is_err() {
+                break;
+            }
+        }
+    }
+    
+    let _ = outgoing.close().await;
+}
+
+// Apply one client command, returning the message to send back, if any
+fn handle_command(text: &str, state: &ServerState, subscribed: &mut HashSet<String>) -> Option<serde_json::Value> {
+    let command = match serde_json::from_str::<WsCommand>(text) {
+        Ok(command) => command,
+        Err(e) => return Some(serde_json::json!({ "type": "error", "message": format!("Invalid command: {}", e) })),
+    };
+    
+    match command {
+        WsCommand::Subscribe { id } => {
+            // Start from the current state so the client needn't poll once
+            let current = state.get_progress(&id);
+            subscribed.insert(id.clone());
+            current.map(|progress| serde_json::json!({ "type": "progress", "id": id, "progress": progress }))
+        }
+        WsCommand::Cancel { id } => {
+            let success = state.cancel_operation(&id);
+            Some(serde_json::json!({ "type": "cancelled", "id": id, "success": success }))
+        }
+    }
+}
@@ -1,155 +1,381 @@
+use std::collections::{HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io;
 use std::io::{Write, Read, BufReader, BufWriter};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 use tempfile::tempdir;
 use walkdir::WalkDir;
 
 use crate::state::ServerState;
 use crate::models::ZipProgress;
 
-// Count files in a directory recursively
-pub fn count_files_in_directory(dir: &Path) -> usize {
-    let mut count = 0;
+// How much an archive of a directory has to process
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DirectoryTotals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl DirectoryTotals {
+    // Share of the work done: by bytes, so one huge file among many small
+    // ones doesn't make the bar jump, or by files for all-empty trees
+    pub fn percentage(&self, processed_files: usize, processed_bytes: u64) -> f32 {
+        if self.bytes > 0 {
+            (processed_bytes as f64 / self.bytes as f64 * 100.0) as f32
+        } else if self.files > 0 {
+            (processed_files as f32 / self.files as f32) * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+// Count files and sum their sizes under a directory. Works through a
+// queue rather than recursing, so deep trees can't overflow the stack, and
+// visits each real directory once, so symlink loops end. Symlinks follow
+// walk_archive_entries: never out of the root, and into linked directories
+// only with --follow-symlinks
+pub fn count_files_in_directory(dir: &Path, state: &ServerState) -> DirectoryTotals {
+    let follow_symlinks = state.config().follow_symlinks;
+    let mut totals = DirectoryTotals::default();
+    let mut visited = HashSet::new();
+    let mut pending = VecDeque::from([dir.to_path_buf()]);
     
-    if let Ok(entries) = fs::read_dir(dir) {
+    while let Some(dir) = pending.pop_front() {
+        // Skip directories that are gone or already counted via another path
+        let first_visit = fs::canonicalize(&dir).is_ok_and(|canonical| visited.insert(canonical));
+        if !first_visit {
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        
         for entry in entries.flatten() {
             let path = entry.path();
-            if path.is_file() {
-                count += 1;
-            } else if path.is_dir() {
-                count += count_files_in_directory(&path);
+            if state.is_excluded(&path) {
+                continue;
+            }
+            let is_symlink = entry.file_type().is_ok_and(|file_type| file_type.is_symlink());
+            if is_symlink && !state.is_path_allowed(&path) {
+                continue;
+            }
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_file() {
+                totals.files += 1;
+                totals.bytes += metadata.len();
+            } else if metadata.is_dir() && (follow_symlinks || !is_symlink) {
+                pending.push_back(path);
             }
         }
     }
     
-    count
+    totals
+}
+
+// Totals recorded for an operation by /api/zip/init, or counted now
+pub fn archive_totals(state: &ServerState, operation_id: &str, base_dir: &Path) -> DirectoryTotals {
+    match state.get_progress(operation_id) {
+        Some(progress) if progress.total_files > 0 => DirectoryTotals {
+            files: progress.total_files,
+            bytes: progress.total_bytes,
+        },
+        _ => count_files_in_directory(base_dir, state),
+    }
 }
 
-// High-performance ZIP archive creation using multiple threads
+// What goes into a ZIP: everything under a directory, or an explicit
+// list of already-validated files
+enum ArchiveSource {
+    Directory(PathBuf),
+    Files(Vec<PathBuf>),
+}
+
+// High-performance ZIP archive creation using multiple threads. Returns
+// the files that had to be skipped
 pub async fn create_zip_archive(
     root_dir: impl AsRef<Path>,
     base_dir: impl AsRef<Path>,
     output_path: impl AsRef<Path>,
     operation_id: String,
+    compression: CompressionLevel,
     state: ServerState,
-) -> io::Result<()> {
+) -> io::Result<Vec<String>> {
+    let source = ArchiveSource::Directory(base_dir.as_ref().to_path_buf());
+    build_zip_archive(root_dir, source, output_path, operation_id, compression, state).await
+}
+
+// Archive just the given files, skipping the directory walk; entry names
+// are relative to root_dir
+pub async fn create_zip_archive_from_files(
+    root_dir: impl AsRef<Path>,
+    files: Vec<PathBuf>,
+    output_path: impl AsRef<Path>,
+    operation_id: String,
+    compression: CompressionLevel,
+    state: ServerState,
+) -> io::Result<Vec<String>> {
+    build_zip_archive(root_dir, ArchiveSource::Files(files), output_path, operation_id, compression, state).await
+}
+
+async fn build_zip_archive(
+    root_dir: impl AsRef<Path>,
+    source: ArchiveSource,
+    output_path: impl AsRef<Path>,
+    operation_id: String,
+    compression: CompressionLevel,
+    state: ServerState,
+) -> io::Result<Vec<String>> {
     // Convert to owned values that can be moved into the closure
     let root_dir = root_dir.as_ref().to_path_buf();
-    let base_dir = base_dir.as_ref().to_path_buf();
     let output_path = output_path.as_ref().to_path_buf();
     
+    // Taken before the blocking task starts so a cancellation that lands
+    // while it is still queued isn't lost
+    let cancelled = state.cancel_flag(&operation_id);
+    
     tokio::task::spawn_blocking(move || {
-        // Get total files first
-        let total_files = match state.get_progress(&operation_id) {
-            Some(progress) if progress.total_files > 0 => progress.total_files,
-            _ => count_files_in_directory(&base_dir),
+        // Get totals first
+        let totals = match &source {
+            ArchiveSource::Directory(base_dir) => archive_totals(&state, &operation_id, base_dir),
+            ArchiveSource::Files(files) => totals_for_files(files),
         };
         
         // Initialize progress
         state.update_progress(&operation_id, ZipProgress {
-            current_file: "Initializing high-performance compression...".to_string(),
+            current_file: format!("Initializing high-performance compression ({})...", compression.name()),
             processed_files: 0,
-            total_files,
+            total_files: totals.files,
+            total_bytes: totals.bytes,
             percentage: 0.0,
+            ..Default::default()
         });
 
+        let options = EntryOptions::new(compression, state.config().deterministic);
+        
         // Create shared progress trackers
-        let processed_count = Arc::new(AtomicUsize::new(0));
-        let current_file = Arc::new(Mutex::new(String::new()));
+        let counters = ProgressCounters::default();
+        let tracking_done = Arc::new(AtomicBool::new(false));
+        check_cancelled(&cancelled)?;
+        
+        // Small archives are written straight to the output on this
+        // thread: temp segments, the worker pool and a progress thread
+        // would cost more than they save
+        if totals.files < SMALL_ARCHIVE_FILES && totals.bytes < SMALL_ARCHIVE_BYTES {
+            let (file_groups, directories) = archive_entries(&source, &root_dir, &state)?;
+            let output = BufWriter::new(fs::File::create(&output_path).map_err(|e| with_context(e, "Cannot create archive file"))?);
+            write_single_archive(&file_groups.concat(), &directories, output, &root_dir, options, &counters, &cancelled)?.flush()?;
+            let skipped = counters.take_skipped();
+            state.update_progress(&operation_id, complete_progress(totals, &skipped));
+            return Ok(skipped);
+        }
         
         // Create temp directory for intermediate files
-        let temp_dir = tempdir()?;
+        let temp_dir = tempdir().map_err(|e| with_context(e, "Cannot create temporary folder"))?;
         
         // Start progress tracking thread
         let progress_handle = start_progress_tracking(
             operation_id.clone(), 
             state.clone(), 
-            processed_count.clone(), 
-            current_file.clone(),
-            total_files
+            counters.clone(),
+            tracking_done.clone(),
+            totals
         );
         
-        // Group files by directory for better locality and compression
-        let file_groups = collect_files_by_directory(&base_dir, &root_dir)?;
-        
-        // Get optimal compression level for speed
-        let compression = determine_optimal_compression();
-        
-        // Create temporary ZIP segments in parallel
-        let segment_paths: Vec<PathBuf> = process_file_groups_in_parallel(
-            &file_groups, 
-            &temp_dir.path(),
-            &root_dir, 
-            compression, 
-            processed_count.clone(),
-            current_file.clone()
-        )?;
-        
-        // Merge ZIP segments into final archive
-        merge_zip_segments(
-            segment_paths, 
-            &output_path, 
-            &operation_id, 
-            state.clone()
-        )?;
-        
-        // Signal progress thread to finish and wait for it
+        let result = (|| {
+            let (file_groups, directories) = archive_entries(&source, &root_dir, &state)?;
+            
+            // With a single group there is nothing to parallelise, so skip
+            // the segment round-trip and write the final archive directly
+            if file_groups.len() <= 1 {
+                let output = BufWriter::new(fs::File::create(&output_path).map_err(|e| with_context(e, "Cannot create archive file"))?);
+                return write_single_archive(
+                    file_groups.first().map(Vec::as_slice).unwrap_or_default(),
+                    &directories,
+                    output,
+                    &root_dir,
+                    options,
+                    &counters,
+                    &cancelled
+                )?.flush();
+            }
+            
+            // Create temporary ZIP segments in parallel
+            let segment_paths: Vec<PathBuf> = process_file_groups_in_parallel(
+                &file_groups, 
+                temp_dir.path(),
+                &root_dir, 
+                options, 
+                &counters,
+                &cancelled
+            )?;
+            
+            // Merge ZIP segments into final archive
+            merge_zip_segments(
+                segment_paths, 
+                &directories,
+                &output_path, 
+                &operation_id, 
+                state.clone(),
+                totals,
+                &cancelled
+            )
+        })();
+        
+        // Signal progress thread to finish and wait for it, even on failure
+        tracking_done.store(true, Ordering::Relaxed);
         let _ = progress_handle.join();
+        if cancelled.load(Ordering::Relaxed) {
+            // The tracker may have re-published progress after the
+            // requester cleaned up; nobody is waiting for it now
+            state.remove_progress(&operation_id);
+        }
+        result?;
 
         // Final update
-        state.update_progress(&operation_id, ZipProgress {
-            current_file: "ZIP archive complete".to_string(),
-            processed_files: total_files,
-            total_files,
-            percentage: 100.0,
-        });
+        let skipped = counters.take_skipped();
+        state.update_progress(&operation_id, complete_progress(totals, &skipped));
         
-        Ok(())
+        Ok(skipped)
+    }).await?
+}
+
+// Build a ZIP of base_dir in memory, for folders under --mem-zip-limit.
+// It's always written in one pass, so its bytes match create_zip_archive
+// only for folders under the small-archive limits; bigger ones go through
+// segments on disk and can come out laid out differently
+pub async fn create_zip_in_memory(
+    base_dir: PathBuf,
+    operation_id: String,
+    compression: CompressionLevel,
+    state: ServerState,
+) -> io::Result<(Vec<u8>, Vec<String>)> {
+    let cancelled = state.cancel_flag(&operation_id);
+    
+    tokio::task::spawn_blocking(move || {
+        let totals = archive_totals(&state, &operation_id, &base_dir);
+        let options = EntryOptions::new(compression, state.config().deterministic);
+        let counters = ProgressCounters::default();
+        
+        let source = ArchiveSource::Directory(base_dir.clone());
+        let (file_groups, directories) = archive_entries(&source, &base_dir, &state)?;
+        let output = io::Cursor::new(Vec::new());
+        let output = write_single_archive(&file_groups.concat(), &directories, output, &base_dir, options, &counters, &cancelled)?;
+        
+        let skipped = counters.take_skipped();
+        state.update_progress(&operation_id, complete_progress(totals, &skipped));
+        Ok((output.into_inner(), skipped))
     }).await?
 }
 
+// Below both of these an archive is built without temp segments
+const SMALL_ARCHIVE_FILES: usize = 50;
+const SMALL_ARCHIVE_BYTES: u64 = 1024 * 1024;
+
+// Files grouped by directory for better locality and compression, and the
+// directory entries (relative, with a trailing slash) the archive needs
+fn archive_entries(source: &ArchiveSource, root_dir: &Path, state: &ServerState) -> io::Result<(Vec<Vec<PathBuf>>, Vec<String>)> {
+    let (file_groups, directories) = match source {
+        // Balancing reorders the groups; --deterministic keeps walk order
+        ArchiveSource::Directory(base_dir) => collect_files_by_directory(base_dir, state, !state.config().deterministic)?,
+        ArchiveSource::Files(files) => (group_selected_files(files), Vec::new()),
+    };
+    let directories = directories.iter()
+        .map(|dir| dir.strip_prefix(root_dir).unwrap_or(dir).to_string_lossy().to_string())
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| ensure_trailing_slash(&dir))
+        .collect();
+    Ok((file_groups, directories))
+}
+
+fn complete_progress(totals: DirectoryTotals, skipped: &[String]) -> ZipProgress {
+    ZipProgress {
+        current_file: "ZIP archive complete".to_string(),
+        processed_files: totals.files,
+        total_files: totals.files,
+        processed_bytes: totals.bytes,
+        total_bytes: totals.bytes,
+        percentage: 100.0,
+        skipped: skipped.to_vec(),
+        ..Default::default()
+    }
+}
+
+// Shared between the compression workers and the progress tracker
+#[derive(Clone, Default)]
+struct ProgressCounters {
+    processed_files: Arc<AtomicUsize>,
+    processed_bytes: Arc<AtomicU64>,
+    current_file: Arc<Mutex<String>>,
+    skipped: Arc<Mutex<Vec<String>>>,
+}
+
+impl ProgressCounters {
+    fn skipped(&self) -> Vec<String> {
+        self.skipped.lock().unwrap().clone()
+    }
+    
+    fn take_skipped(&self) -> Vec<String> {
+        std::mem::take(&mut *self.skipped.lock().unwrap())
+    }
+}
+
+// Share of the progress bar given to compressing; merging segments into
+// the final archive fills the rest
+const COMPRESSION_SHARE: f32 = 90.0;
+
 // Start a background thread to track and report progress
 fn start_progress_tracking(
     operation_id: String,
     state: ServerState,
-    processed_count: Arc<AtomicUsize>,
-    current_file: Arc<Mutex<String>>,
-    total_files: usize
+    counters: ProgressCounters,
+    done: Arc<AtomicBool>,
+    totals: DirectoryTotals
 ) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        let update_interval = std::time::Duration::from_millis(100);
+        let update_interval = Duration::from_millis(100);
+        let mut last_bytes = 0;
         let mut last_processed = 0;
+        let mut throughput = ThroughputMeter::new();
         
         loop {
-            let processed = processed_count.load(Ordering::Relaxed);
+            let processed = counters.processed_files.load(Ordering::Relaxed);
+            let bytes = counters.processed_bytes.load(Ordering::Relaxed);
+            let bytes_per_sec = throughput.sample(bytes);
             
             // Only update if there's a change
-            if processed != last_processed {
-                let percentage = if total_files > 0 {
-                    (processed as f32 / total_files as f32) * 100.0
-                } else {
-                    0.0
-                };
-                
-                let current = current_file.lock().unwrap().clone();
+            if processed != last_processed || bytes != last_bytes {
+                let current = counters.current_file.lock().unwrap().clone();
                 
                 state.update_progress(&operation_id, ZipProgress {
                     current_file: current,
                     processed_files: processed,
-                    total_files,
-                    percentage,
+                    total_files: totals.files,
+                    processed_bytes: bytes,
+                    total_bytes: totals.bytes,
+                    percentage: totals.percentage(processed, bytes) * COMPRESSION_SHARE / 100.0,
+                    bytes_per_sec: bytes_per_sec as u64,
+                    eta_secs: eta_secs(bytes_per_sec, totals.bytes.saturating_sub(bytes)),
+                    skipped: counters.skipped(),
+                    ..Default::default()
                 });
                 
                 last_processed = processed;
+                last_bytes = bytes;
             }
             
-            // Exit if all files processed
-            if processed >= total_files {
+            // Exit if all files processed or the archive stopped early
+            if processed >= totals.files || done.load(Ordering::Relaxed) {
                 break;
             }
             
@@ -158,17 +384,97 @@ fn start_progress_tracking(
     })
 }
 
-// Collect files grouped by directory to improve compression efficiency
-fn collect_files_by_directory(base_dir: &Path, _root_dir: &Path) -> io::Result<Vec<Vec<PathBuf>>> {
+// How far back throughput samples are averaged
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(3);
+
+// Moving average of bytes per second over the last few seconds
+#[derive(Default)]
+pub struct ThroughputMeter {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ThroughputMeter {
+    pub fn new() -> Self {
+        Self { samples: VecDeque::new() }
+    }
+    
+    // Record the bytes processed so far and return the recent rate
+    pub fn sample(&mut self, bytes: u64) -> f64 {
+        let now = Instant::now();
+        self.samples.push_back((now, bytes));
+        while self.samples.len() > 2 && now.duration_since(self.samples[0].0) > THROUGHPUT_WINDOW {
+            self.samples.pop_front();
+        }
+        
+        let (oldest_at, oldest_bytes) = self.samples[0];
+        let elapsed = now.duration_since(oldest_at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        bytes.saturating_sub(oldest_bytes) as f64 / elapsed
+    }
+}
+
+// Seconds left at the given rate; unknown until something has moved
+pub fn eta_secs(bytes_per_sec: f64, remaining_bytes: u64) -> Option<u64> {
+    if bytes_per_sec < 1.0 {
+        return None;
+    }
+    Some((remaining_bytes as f64 / bytes_per_sec).ceil() as u64)
+}
+
+// Everything under base_dir that belongs in an archive, in name order:
+// excluded paths are pruned and symlinks that lead outside the served
+// root are left out
+pub fn walk_archive_entries<'a>(base_dir: &Path, state: &'a ServerState) -> impl Iterator<Item = walkdir::DirEntry> + 'a {
+    WalkDir::new(base_dir)
+        .sort_by_file_name()
+        .follow_links(state.config().follow_symlinks)
+        .into_iter()
+        .filter_entry(|e| !state.is_excluded(e.path()))
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.path_is_symlink() || state.is_path_allowed(e.path()))
+}
+
+// Hash of everything that goes into a --deterministic ZIP of `base_dir`:
+// each entry's name, type, size and mtime, plus the compression level.
+// Used as the download's ETag, so it changes whenever the bytes would
+pub fn archive_fingerprint(base_dir: &Path, state: &ServerState, compression: CompressionLevel) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(compression.name().as_bytes());
+    for entry in walk_archive_entries(base_dir, state) {
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let mtime = metadata.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        let rel_path = entry.path().strip_prefix(base_dir).unwrap_or(entry.path());
+        hasher.update(rel_path.to_string_lossy().as_bytes());
+        hasher.update([0, metadata.is_dir() as u8]);
+        hasher.update(metadata.len().to_le_bytes());
+        hasher.update(mtime.to_le_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+// Collect files grouped by directory to improve compression efficiency,
+// along with every directory so empty ones survive into the archive
+fn collect_files_by_directory(base_dir: &Path, state: &ServerState, balance: bool) -> io::Result<(Vec<Vec<PathBuf>>, Vec<PathBuf>)> {
     let mut directory_groups: Vec<Vec<PathBuf>> = Vec::new();
+    let mut directories = Vec::new();
     let mut current_dir = PathBuf::new();
     let mut current_group = Vec::new();
     
     // Walk the directory tree
-    for entry in WalkDir::new(base_dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+    for entry in walk_archive_entries(base_dir, state) {
         let path = entry.path().to_path_buf();
         
-        if path.is_file() {
+        if path.is_dir() {
+            directories.push(path);
+        } else if path.is_file() {
             // If we moved to a new directory, start a new group
             let parent = path.parent().unwrap_or(Path::new(""));
             if !current_dir.as_os_str().is_empty() && parent != current_dir {
@@ -191,9 +497,36 @@ fn collect_files_by_directory(base_dir: &Path, _root_dir: &Path) -> io::Result<V
     }
     
     // Balance groups for optimal parallel processing
-    balance_file_groups(&mut directory_groups);
+    if balance {
+        balance_file_groups(&mut directory_groups);
+    }
     
-    Ok(directory_groups)
+    Ok((directory_groups, directories))
+}
+
+// Count and size an explicit file selection
+fn totals_for_files(files: &[PathBuf]) -> DirectoryTotals {
+    DirectoryTotals {
+        files: files.len(),
+        bytes: files.iter().map(|f| fs::metadata(f).map(|m| m.len()).unwrap_or(0)).sum(),
+    }
+}
+
+// Group a file selection by parent directory, as the directory walk does
+fn group_selected_files(files: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut sorted = files.to_vec();
+    sorted.sort();
+    
+    let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+    for file in sorted {
+        match groups.last_mut() {
+            Some(group) if group[0].parent() == file.parent() => group.push(file),
+            _ => groups.push(vec![file]),
+        }
+    }
+    
+    balance_file_groups(&mut groups);
+    groups
 }
 
 // Balance file groups to ensure efficient parallel processing
@@ -204,7 +537,7 @@ fn balance_file_groups(groups: &mut Vec<Vec<PathBuf>>) {
     // If we have too few groups, split larger ones
     if groups.len() < target_groups {
         // Sort groups by size (largest first)
-        groups.sort_by(|a, b| b.len().cmp(&a.len()).reverse());
+        groups.sort_by_key(|group| group.len());
         
         // Calculate how many more groups we need
         let additional_groups_needed = target_groups - groups.len();
@@ -222,7 +555,7 @@ fn balance_file_groups(groups: &mut Vec<Vec<PathBuf>>) {
     // If we have too many small groups, combine them
     else if groups.len() > target_groups * 2 {
         // Sort by size (smallest first)
-        groups.sort_by(|a, b| a.len().cmp(&b.len()));
+        groups.sort_by_key(|group| group.len());
         
         // Combine smallest groups until we reach target_groups
         while groups.len() > target_groups {
@@ -239,10 +572,78 @@ fn balance_file_groups(groups: &mut Vec<Vec<PathBuf>>) {
     }
 }
 
-// Determine the optimal compression level for maximum speed
-fn determine_optimal_compression() -> zip::CompressionMethod {
-    // Fastest compression method for speed
-    zip::CompressionMethod::Deflated
+// Compression presets selectable with --compression
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CompressionLevel {
+    Store,
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl CompressionLevel {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "store" => Some(CompressionLevel::Store),
+            "fast" => Some(CompressionLevel::Fast),
+            "default" => Some(CompressionLevel::Default),
+            "best" => Some(CompressionLevel::Best),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionLevel::Store => "store",
+            CompressionLevel::Fast => "fast",
+            CompressionLevel::Default => "default",
+            CompressionLevel::Best => "best",
+        }
+    }
+
+    // Map the preset onto a zip method and deflate level
+    fn file_options(&self) -> zip::write::FileOptions {
+        let options = zip::write::FileOptions::default();
+        match self {
+            CompressionLevel::Store => options.compression_method(zip::CompressionMethod::Stored),
+            CompressionLevel::Fast => options
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(1)),
+            CompressionLevel::Default => options.compression_method(zip::CompressionMethod::Deflated),
+            CompressionLevel::Best => options
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(9)),
+        }
+    }
+}
+
+// Options for file and directory entries. Entries not stamped with a
+// file's own mtime get the current time, except under --deterministic,
+// where it's pinned to 1980-01-01 so a rebuild gives the same bytes
+#[derive(Clone, Copy)]
+struct EntryOptions {
+    files: zip::write::FileOptions,
+    directories: zip::write::FileOptions,
+}
+
+impl EntryOptions {
+    fn new(compression: CompressionLevel, deterministic: bool) -> Self {
+        let mut files = compression.file_options();
+        if deterministic {
+            files = files.last_modified_time(zip::DateTime::default());
+        }
+        Self { files, directories: directory_options(deterministic) }
+    }
+}
+
+fn directory_options(deterministic: bool) -> zip::write::FileOptions {
+    let options = zip::write::FileOptions::default().unix_permissions(0o755);
+    if deterministic {
+        options.last_modified_time(zip::DateTime::default())
+    } else {
+        options
+    }
 }
 
 // Process file groups in parallel, creating separate ZIP segments
@@ -250,109 +651,233 @@ fn process_file_groups_in_parallel(
     file_groups: &[Vec<PathBuf>],
     temp_dir: &Path,
     root_dir: &Path,
-    compression: zip::CompressionMethod,
-    processed_count: Arc<AtomicUsize>,
-    current_file: Arc<Mutex<String>>,
+    options: EntryOptions,
+    counters: &ProgressCounters,
+    cancelled: &AtomicBool,
 ) -> io::Result<Vec<PathBuf>> {
-    let options = zip::write::FileOptions::default()
-        .compression_method(compression)
-        .unix_permissions(0o755);
-    
-    // Create a segment path for each group
-    let segment_paths: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    // Create a segment path for each group, numbered so they're merged in
+    // group order however the workers finish
+    let segment_paths: Mutex<Vec<(usize, PathBuf)>> = Mutex::new(Vec::new());
     
     // Process each group in parallel
-    file_groups.par_iter().try_for_each(|group| -> io::Result<()> {
+    file_groups.par_iter().enumerate().try_for_each(|(index, group)| -> io::Result<()> {
         // Create a unique segment file
         let segment_path = temp_dir.join(format!("segment_{}.zip", fastrand::u64(..)));
         
         // Create ZIP writer for this segment
-        let file = BufWriter::new(fs::File::create(&segment_path)?);
+        let file = BufWriter::new(fs::File::create(&segment_path).map_err(|e| with_context(e, "Cannot create temporary file"))?);
         let mut zip = zip::ZipWriter::new(file);
+        let mut added_dirs = HashSet::new();
         
-        // Process each file in this group
-        for file_path in group {
-            // Calculate relative path
-            let rel_path = file_path.strip_prefix(root_dir)
-                .unwrap_or(file_path)
-                .to_string_lossy()
-                .to_string();
-            
-            // Update current file name for progress
-            {
-                let mut current = current_file.lock().unwrap();
-                *current = rel_path.clone();
-            }
-            
-            // Handle directory entries
-            if let Some(parent) = file_path.parent() {
-                let parent_rel = parent.strip_prefix(root_dir)
-                    .unwrap_or(parent)
-                    .to_string_lossy();
-                
-                if !parent_rel.is_empty() {
-                    let dir_path = ensure_trailing_slash(&parent_rel);
-                    // Only try to add directory if it's not root or already added
-                    // This is a simple approach - in a real implementation you'd track added directories
-                    if !dir_path.is_empty() && dir_path != "/" {
-                        let _ = zip.add_directory(dir_path, options);
-                    }
-                }
-            }
-            
-            // Add file to ZIP using streaming to reduce memory usage
-            zip.start_file(rel_path, options)?;
-            
-            // Stream file in chunks
-            let mut buffer = vec![0; 64 * 1024];  // 64KB buffer
-            let mut file = BufReader::new(fs::File::open(file_path)?);
-            
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 { break; }
-                zip.write_all(&buffer[..bytes_read])?;
-            }
-            
-            // Update progress counter
-            processed_count.fetch_add(1, Ordering::Relaxed);
-        }
+        write_group(&mut zip, &mut added_dirs, group, root_dir, options.files, counters, cancelled)?;
         
         // Finish this segment
         zip.finish()?;
         
         // Add segment path to the list
-        segment_paths.lock().unwrap().push(segment_path);
+        segment_paths.lock().unwrap().push((index, segment_path));
         
         Ok(())
     })?;
     
-    Ok(segment_paths.into_inner().unwrap())
+    let mut segment_paths = segment_paths.into_inner().unwrap();
+    segment_paths.sort_by_key(|(index, _)| *index);
+    Ok(segment_paths.into_iter().map(|(_, path)| path).collect())
+}
+
+// Write one group of files into a ZIP, with an entry for each enclosing
+// directory not yet in added_dirs
+fn write_group<W: Write + io::Seek>(
+    zip: &mut zip::ZipWriter<W>,
+    added_dirs: &mut HashSet<String>,
+    group: &[PathBuf],
+    root_dir: &Path,
+    options: zip::write::FileOptions,
+    counters: &ProgressCounters,
+    cancelled: &AtomicBool,
+) -> io::Result<()> {
+    for file_path in group {
+        check_cancelled(cancelled)?;
+        
+        // Calculate relative path
+        let rel_path = file_path.strip_prefix(root_dir)
+            .unwrap_or(file_path)
+            .to_string_lossy()
+            .to_string();
+        
+        // Update current file name for progress
+        {
+            let mut current = counters.current_file.lock().unwrap();
+            *current = rel_path.clone();
+        }
+        
+        // A file deleted or made unreadable since the walk is left out
+        // rather than failing the whole archive
+        let opened = fs::File::open(file_path).and_then(|file| {
+            let metadata = file.metadata()?;
+            Ok((file, metadata))
+        });
+        let (file, metadata) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                eprintln!("Skipping {} in archive: {}", rel_path, e);
+                counters.skipped.lock().unwrap().push(rel_path);
+                counters.processed_files.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        
+        add_parent_directories(zip, added_dirs, file_path, root_dir, options)?;
+        
+        // Add file to ZIP using streaming to reduce memory usage,
+        // keeping its own permissions and modification time
+        zip.start_file(rel_path.as_str(), entry_options(options, &metadata))?;
+        
+        // Stream file in chunks
+        let mut buffer = vec![0; 64 * 1024];  // 64KB buffer
+        let mut file = BufReader::new(file);
+        
+        loop {
+            let bytes_read = file.read(&mut buffer).map_err(|e| with_context(e, &format!("Cannot read {}", rel_path)))?;
+            if bytes_read == 0 { break; }
+            zip.write_all(&buffer[..bytes_read])?;
+            counters.processed_bytes.fetch_add(bytes_read as u64, Ordering::Relaxed);
+        }
+        
+        // Update progress counter
+        counters.processed_files.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    Ok(())
+}
+
+// Write the final archive in one pass, for selections too small to split
+fn write_single_archive<W: Write + io::Seek>(
+    group: &[PathBuf],
+    directories: &[String],
+    output: W,
+    root_dir: &Path,
+    options: EntryOptions,
+    counters: &ProgressCounters,
+    cancelled: &AtomicBool,
+) -> io::Result<W> {
+    check_cancelled(cancelled)?;
+    let mut zip = zip::ZipWriter::new(output);
+    
+    let mut added_dirs = HashSet::new();
+    for dir in directories {
+        if added_dirs.insert(dir.clone()) {
+            zip.add_directory(dir.as_str(), options.directories)?;
+        }
+    }
+    
+    write_group(&mut zip, &mut added_dirs, group, root_dir, options.files, counters, cancelled)?;
+    Ok(zip.finish()?)
+}
+
+// Options for one file's entry: its mode and mtime on top of the preset
+fn entry_options(options: zip::write::FileOptions, metadata: &fs::Metadata) -> zip::write::FileOptions {
+    let options = options.unix_permissions(file_mode(metadata));
+    
+    // ZIP timestamps only cover 1980-2107; anything else keeps the default
+    let modified = metadata.modified()
+        .ok()
+        .and_then(|time| zip::DateTime::try_from(time::OffsetDateTime::from(time)).ok());
+    match modified {
+        Some(modified) => options.last_modified_time(modified),
+        None => options,
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+// Without Unix modes, only the read-only flag can be carried over
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+// Add entries for every directory between root_dir and file_path that
+// this segment hasn't written yet, outermost first
+fn add_parent_directories<W: Write + io::Seek>(
+    zip: &mut zip::ZipWriter<W>,
+    added_dirs: &mut HashSet<String>,
+    file_path: &Path,
+    root_dir: &Path,
+    options: zip::write::FileOptions,
+) -> io::Result<()> {
+    let rel_parent = match file_path.parent() {
+        Some(parent) => parent.strip_prefix(root_dir).unwrap_or(parent),
+        None => return Ok(()),
+    };
+    
+    let mut ancestors: Vec<&Path> = rel_parent.ancestors()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect();
+    ancestors.reverse();
+    
+    for dir in ancestors {
+        let dir_path = ensure_trailing_slash(&dir.to_string_lossy());
+        if dir_path != "/" && added_dirs.insert(dir_path.clone()) {
+            zip.add_directory(dir_path, options)?;
+        }
+    }
+    
+    Ok(())
 }
 
 // Merge multiple ZIP segments into a final archive
 fn merge_zip_segments(
     segment_paths: Vec<PathBuf>,
+    directories: &[String],
     output_path: &Path,
     operation_id: &str,
     state: ServerState,
+    totals: DirectoryTotals,
+    cancelled: &AtomicBool,
 ) -> io::Result<()> {
-    // Update status
-    state.update_progress(operation_id, ZipProgress {
-        current_file: "Merging ZIP segments...".to_string(),
-        processed_files: 0,
-        total_files: 0,
-        percentage: 95.0,  // Show high percentage since most work is done
-    });
+    // Progress through the merge is measured in compressed bytes copied
+    let merge_bytes: u64 = segment_paths.iter()
+        .map(|path| fs::metadata(path).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    let mut copied_bytes = 0;
+    let mut last_update = Instant::now();
+    let report = |current_file: String, copied_bytes: u64| {
+        let merged = if merge_bytes > 0 {
+            (copied_bytes as f64 / merge_bytes as f64).min(1.0) as f32
+        } else {
+            1.0
+        };
+        state.update_progress(operation_id, ZipProgress {
+            current_file,
+            processed_files: totals.files,
+            total_files: totals.files,
+            processed_bytes: totals.bytes,
+            total_bytes: totals.bytes,
+            percentage: COMPRESSION_SHARE + merged * (100.0 - COMPRESSION_SHARE),
+            ..Default::default()
+        });
+    };
+    report("Merging ZIP segments...".to_string(), 0);
     
     // Create the final ZIP file
-    let file = BufWriter::new(fs::File::create(output_path)?);
+    check_cancelled(cancelled)?;
+    let file = BufWriter::new(fs::File::create(output_path).map_err(|e| with_context(e, "Cannot create archive file"))?);
     let mut zip = zip::ZipWriter::new(file);
-    let options = zip::write::FileOptions::default()
-        .compression_method(zip::CompressionMethod::Stored); // No need to compress again
     
-    // Process multiple segments in a fast streaming approach
-    let buffer_size = 1024 * 1024; // 1MB buffer for faster copying
-    let mut buffer = vec![0; buffer_size];
+    // Write every directory up front, parents first, so empty ones are
+    // kept; segments share parent directories, so keep one entry for each
+    let mut added_dirs = HashSet::new();
+    let dir_options = directory_options(state.config().deterministic);
+    for dir in directories {
+        if added_dirs.insert(dir.clone()) {
+            zip.add_directory(dir.as_str(), dir_options)?;
+        }
+    }
     
     for path in segment_paths {
         // Extract files from this segment and add to final ZIP
@@ -360,23 +885,21 @@ fn merge_zip_segments(
         let mut segment_reader = zip::ZipArchive::new(segment_file)?;
         
         for i in 0..segment_reader.len() {
-            let mut segment_entry = segment_reader.by_index(i)?;
-            let entry_name = segment_entry.name().to_string();
+            check_cancelled(cancelled)?;
             
-            // Skip directories in the merge phase
-            if segment_entry.is_dir() {
+            let segment_entry = segment_reader.by_index_raw(i)?;
+            
+            if segment_entry.is_dir() && !added_dirs.insert(segment_entry.name().to_string()) {
                 continue;
             }
             
-            // Add the file to our final ZIP
-            zip.start_file(entry_name, options)?;
-            
-            // Stream the file data
-            loop {
-                let bytes_read = segment_entry.read(&mut buffer)?;
-                if bytes_read == 0 { break; }
-                zip.write_all(&buffer[..bytes_read])?;
+            // Copy the already-compressed data as-is so the chosen level is kept
+            copied_bytes += segment_entry.compressed_size();
+            if last_update.elapsed() >= Duration::from_millis(100) {
+                report(format!("Merging {}", segment_entry.name()), copied_bytes);
+                last_update = Instant::now();
             }
+            zip.raw_copy_file(segment_entry)?;
         }
         
         // Clean up this segment file
@@ -389,6 +912,19 @@ fn merge_zip_segments(
     Ok(())
 }
 
+// Abort with an `Interrupted` error once the operation has been cancelled
+fn check_cancelled(cancelled: &AtomicBool) -> io::Result<()> {
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "ZIP operation cancelled"));
+    }
+    Ok(())
+}
+
+// Say what was being done when an I/O error happened, keeping its kind
+pub fn with_context(e: io::Error, context: &str) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", context, e))
+}
+
 // Helper function to ensure directory paths end with slash
 fn ensure_trailing_slash(path: &str) -> String {
     if path.ends_with('/') || path.is_empty() {
@@ -397,3 +933,279 @@ fn ensure_trailing_slash(path: &str) -> String {
         format!("{}/", path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    
+    #[test]
+    fn eta_is_unknown_without_throughput() {
+        assert_eq!(eta_secs(0.0, 1_000), None);
+        assert_eq!(eta_secs(0.5, 1_000), None);
+    }
+    
+    #[test]
+    fn eta_rounds_up() {
+        assert_eq!(eta_secs(100.0, 1_000), Some(10));
+        assert_eq!(eta_secs(300.0, 1_000), Some(4));
+        assert_eq!(eta_secs(100.0, 0), Some(0));
+    }
+    
+    fn state_for(root: &Path) -> ServerState {
+        ServerState::new(Config {
+            root_path: root.to_path_buf(),
+            canonical_root: fs::canonicalize(root).unwrap(),
+            ..Config::default()
+        })
+    }
+    
+    #[test]
+    fn counting_a_deep_tree_fits_in_a_small_stack() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deepest = dir.path().to_path_buf();
+        for _ in 0..200 {
+            deepest.push("d");
+        }
+        fs::create_dir_all(&deepest).unwrap();
+        fs::write(deepest.join("leaf.txt"), "leaf").unwrap();
+        fs::write(dir.path().join("top.txt"), "top").unwrap();
+        
+        let state = state_for(dir.path());
+        let root = dir.path().to_path_buf();
+        // Too little stack to recurse two hundred directories deep
+        let totals = thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || count_files_in_directory(&root, &state))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(totals, DirectoryTotals { files: 2, bytes: 7 });
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_counted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/file.txt"), "data").unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+        
+        let totals = count_files_in_directory(dir.path(), &state_for(dir.path()));
+        assert_eq!(totals, DirectoryTotals { files: 1, bytes: 4 });
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn counting_keeps_to_what_the_archive_walks() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.bin"), "x".repeat(1000)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/file.txt"), "data").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("out")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.bin"), dir.path().join("secret.bin")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("sub"), dir.path().join("linked")).unwrap();
+        
+        let state = state_for(dir.path());
+        assert_eq!(count_files_in_directory(dir.path(), &state), DirectoryTotals { files: 1, bytes: 4 });
+        let (groups, _) = collect_files_by_directory(dir.path(), &state, false).unwrap();
+        assert_eq!(groups.concat().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn merged_archive_has_one_entry_per_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        // Enough files per directory that groups get split across segments,
+        // and enough data to take the parallel path rather than the one for
+        // small archives
+        let data = "data".repeat(4096);
+        for sub in ["a", "a/b", "c"] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+            for i in 0..25 {
+                fs::write(root.join(sub).join(format!("{}.txt", i)), &data).unwrap();
+            }
+        }
+        
+        let state = ServerState::new(Config {
+            root_path: root.clone(),
+            canonical_root: fs::canonicalize(&root).unwrap(),
+            ..Config::default()
+        });
+        let output = dir.path().join("out.zip");
+        create_zip_archive(&root, &root, &output, "dedupe".to_string(), CompressionLevel::Fast, state)
+            .await
+            .unwrap();
+        
+        let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+        let mut names = HashSet::new();
+        let mut dirs = Vec::new();
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).unwrap();
+            assert!(names.insert(entry.name().to_string()), "duplicate entry {}", entry.name());
+            if entry.is_dir() {
+                dirs.push(entry.name().to_string());
+            }
+        }
+        dirs.sort();
+        assert_eq!(dirs, ["a/", "a/b/", "c/"]);
+        assert_eq!(names.len(), 3 + 75);
+    }
+    
+    #[tokio::test]
+    async fn empty_directories_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::create_dir_all(root.join("cache/thumbs")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        
+        let state = ServerState::new(Config {
+            root_path: root.clone(),
+            canonical_root: fs::canonicalize(&root).unwrap(),
+            ..Config::default()
+        });
+        let output = dir.path().join("out.zip");
+        create_zip_archive(&root, &root, &output, "empty-dirs".to_string(), CompressionLevel::Fast, state)
+            .await
+            .unwrap();
+        
+        let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+        let mut names: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["cache/", "cache/thumbs/", "logs/", "src/", "src/main.rs"]);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn file_mode_and_mtime_are_kept() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        let secret = root.join("secret.txt");
+        fs::write(&secret, "hush").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o600)).unwrap();
+        let script = root.join("run.sh");
+        fs::write(&script, "#!/bin/sh").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        
+        let state = ServerState::new(Config {
+            root_path: root.clone(),
+            canonical_root: fs::canonicalize(&root).unwrap(),
+            ..Config::default()
+        });
+        let output = dir.path().join("out.zip");
+        create_zip_archive(&root, &root, &output, "modes".to_string(), CompressionLevel::Fast, state)
+            .await
+            .unwrap();
+        
+        let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(archive.by_name("secret.txt").unwrap().unix_mode().unwrap() & 0o777, 0o600);
+        assert_eq!(archive.by_name("run.sh").unwrap().unix_mode().unwrap() & 0o777, 0o755);
+        
+        let modified = time::OffsetDateTime::from(fs::metadata(&secret).unwrap().modified().unwrap());
+        let stored = archive.by_name("secret.txt").unwrap().last_modified();
+        assert_eq!(stored.year() as i32, modified.year());
+        assert_eq!(stored.month(), u8::from(modified.month()));
+        assert_eq!(stored.day(), modified.day());
+    }
+    
+    #[tokio::test]
+    async fn vanished_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("kept.txt"), "kept").unwrap();
+        
+        let output = dir.path().join("out.zip");
+        let files = vec![root.join("kept.txt"), root.join("gone.txt")];
+        let skipped = create_zip_archive_from_files(&root, files, &output, "vanished".to_string(), CompressionLevel::Fast, state_for(&root))
+            .await
+            .unwrap();
+        assert_eq!(skipped, ["gone.txt"]);
+        
+        let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(archive.len(), 1);
+        assert!(archive.by_name("kept.txt").is_ok());
+    }
+    
+    #[tokio::test]
+    async fn deterministic_builds_are_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        // 60 files of 32 KiB is over both small-archive limits, so the
+        // parallel segments and their merge are what's compared
+        let data = "data".repeat(8192);
+        for sub in ["a", "b", "b/c", "empty"] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+        }
+        for sub in ["a", "b", "b/c"] {
+            for i in 0..20 {
+                fs::write(root.join(sub).join(format!("{}.txt", i)), &data).unwrap();
+            }
+        }
+        
+        let state = ServerState::new(Config {
+            root_path: root.clone(),
+            canonical_root: fs::canonicalize(&root).unwrap(),
+            deterministic: true,
+            ..Config::default()
+        });
+        let mut builds = Vec::new();
+        for name in ["first.zip", "second.zip"] {
+            let output = dir.path().join(name);
+            create_zip_archive(&root, &root, &output, name.to_string(), CompressionLevel::Fast, state.clone())
+                .await
+                .unwrap();
+            builds.push(fs::read(&output).unwrap());
+        }
+        assert!(60 >= SMALL_ARCHIVE_FILES && 60 * data.len() as u64 >= SMALL_ARCHIVE_BYTES);
+        assert_eq!(builds[0], builds[1]);
+        assert_eq!(
+            archive_fingerprint(&root, &state, CompressionLevel::Fast),
+            archive_fingerprint(&root, &state, CompressionLevel::Fast)
+        );
+        assert_ne!(
+            archive_fingerprint(&root, &state, CompressionLevel::Fast),
+            archive_fingerprint(&root, &state, CompressionLevel::Best)
+        );
+    }
+    
+    #[tokio::test]
+    async fn in_memory_archive_matches_the_disk_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("sub/empty")).unwrap();
+        fs::write(root.join("a.txt"), "alpha").unwrap();
+        fs::write(root.join("sub/b.txt"), "bravo".repeat(100)).unwrap();
+        
+        let state = ServerState::new(Config {
+            root_path: root.clone(),
+            canonical_root: fs::canonicalize(&root).unwrap(),
+            deterministic: true,
+            ..Config::default()
+        });
+        let output = dir.path().join("disk.zip");
+        create_zip_archive(&root, &root, &output, "disk".to_string(), CompressionLevel::Default, state.clone())
+            .await
+            .unwrap();
+        let (in_memory, skipped) = create_zip_in_memory(root.clone(), "memory".to_string(), CompressionLevel::Default, state)
+            .await
+            .unwrap();
+        
+        assert!(skipped.is_empty());
+        assert_eq!(in_memory, fs::read(&output).unwrap());
+    }
+    
+    #[test]
+    fn first_sample_has_no_rate() {
+        let mut meter = ThroughputMeter::new();
+        assert_eq!(meter.sample(5_000), 0.0);
+    }
+}
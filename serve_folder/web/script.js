@@ -1,4 +1,31 @@
 document.addEventListener('DOMContentLoaded', () => {
+    // URL prefix the server runs under (--base-path), empty at the root
+    const BASE_PATH = document.documentElement.dataset.basePath || '';
+    
+    // Paths of names that aren't valid UTF-8 come as this prefix followed
+    // by percent-encoded bytes, which already form a URL path
+    const RAW_PATH_PREFIX = '~raw:';
+    
+    // Readable form of a percent-encoded raw segment, with invalid bytes
+    // shown as replacement characters
+    const decodeLossy = (encoded) => {
+        const bytes = [];
+        for (let i = 0; i < encoded.length; i++) {
+            if (encoded[i] === '%' && i + 2 < encoded.length) {
+                bytes.push(parseInt(encoded.substr(i + 1, 2), 16));
+                i += 2;
+            } else {
+                bytes.push(encoded.charCodeAt(i));
+            }
+        }
+        return new TextDecoder().decode(new Uint8Array(bytes));
+    };
+    
+    // URL of a served file, relative to the root
+    const fileUrl = (path) => path.startsWith(RAW_PATH_PREFIX)
+        ? `${BASE_PATH}/${path.slice(RAW_PATH_PREFIX.length)}`
+        : `${BASE_PATH}/${path.split('/').map(encodeURIComponent).join('/')}`;
+    
     // Elements
     const fileList = document.getElementById('fileList');
     const breadcrumbs = document.getElementById('breadcrumbs');
@@ -6,29 +33,88 @@ document.addEventListener('DOMContentLoaded', () => {
     const confirmModal = document.getElementById('confirmModal');
     const confirmYesBtn = document.getElementById('confirmYes');
     const confirmNoBtn = document.getElementById('confirmNo');
+    const dropZone = document.getElementById('dropZone');
+    const fileInput = document.getElementById('fileInput');
+    const dirSummary = document.getElementById('dirSummary');
+    const downloadSelectedBtn = document.getElementById('downloadSelected');
+    const toggleGridBtn = document.getElementById('toggleGrid');
+    const newFolderBtn = document.getElementById('newFolder');
     
     // Current path for navigation
     let currentPath = '';
     
+    // Files ticked in the current folder, by path
+    const selectedFiles = new Set();
+    
+    const updateSelectionButton = () => {
+        downloadSelectedBtn.disabled = selectedFiles.size === 0;
+        downloadSelectedBtn.textContent = selectedFiles.size > 0
+            ? `Download selected (${selectedFiles.size})`
+            : 'Download selected';
+    };
+    
+    // Images /api/thumb can preview
+    const THUMBNAIL_PATTERN = /\.(jpe?g|png|gif|webp|bmp)$/i;
+    
+    // Grid view shows larger previews; the choice survives reloads
+    const setGridView = (enabled) => {
+        fileList.classList.toggle('grid', enabled);
+        toggleGridBtn.textContent = enabled ? 'List view' : 'Grid view';
+        localStorage.setItem('gridView', enabled ? '1' : '');
+    };
+    setGridView(localStorage.getItem('gridView') === '1');
+    
+    // Number of entries requested per page
+    const PAGE_SIZE = 500;
+    
+    // Fetch one page of a directory listing
+    const fetchPage = (path, offset) => {
+        return fetch(`${BASE_PATH}/api/list?path=${encodeURIComponent(path)}&offset=${offset}&limit=${PAGE_SIZE}`)
+            .then(response => response.json().then(data => {
+                // Unreadable folders come back as errors, not empty listings
+                if (!response.ok) {
+                    const where = data.current_path !== undefined ? ` in /${data.current_path}` : '';
+                    throw new Error(`${data.message || response.statusText}${where}`);
+                }
+                return data;
+            }));
+    };
+    
     // Load directory contents
     const loadDirectory = (path = '') => {
         fileList.innerHTML = '<div class="loader">Loading...</div>';
         
-        fetch(`/api/list?path=${encodeURIComponent(path)}`)
-            .then(response => response.json())
+        fetchPage(path, 0)
             .then(data => {
                 displayFiles(data);
                 updateBreadcrumbs(data.current_path);
                 currentPath = data.current_path;
+                loadDirectorySize(data.current_path);
             })
             .catch(error => {
                 fileList.innerHTML = `<div class="error">Error loading directory: ${error.message}</div>`;
             });
     };
     
+    // Fetch the recursive size separately so the listing isn't held up
+    const loadDirectorySize = (path) => {
+        dirSummary.textContent = '';
+        fetch(`${BASE_PATH}/api/list?path=${encodeURIComponent(path)}&limit=0&compute_dir_size=true`)
+            .then(response => response.json())
+            .then(data => {
+                if (path !== currentPath || data.total_size == null) {
+                    return;
+                }
+                dirSummary.textContent = `${data.total} item(s), ${formatFileSize(data.total_size)} total`;
+            })
+            .catch(() => {});
+    };
+    
     // Display files in the UI
     const displayFiles = (data) => {
         fileList.innerHTML = '';
+        selectedFiles.clear();
+        updateSelectionButton();
         
         // Add parent directory link if not at root
         if (data.current_path) {
@@ -50,6 +136,11 @@ document.addEventListener('DOMContentLoaded', () => {
             return;
         }
         
+        appendEntries(data, data.entries.length);
+    };
+    
+    // Append a page of entries, with a "load more" item when the server has more
+    const appendEntries = (data, loaded) => {
         data.entries.forEach(entry => {
             const item = document.createElement('div');
             item.className = 'file-item';
@@ -60,7 +151,9 @@ document.addEventListener('DOMContentLoaded', () => {
                     <span class="name">${escapeHtml(entry.name)}</span>
                     <span class="size">Folder</span>
                     <div class="actions">
-                        <button class="action-btn download" title="Download folder as ZIP">📦</button>
+                        <button class="action-btn download download-zip" title="Download folder as ZIP">📦</button>
+                        <button class="action-btn download-targz" title="Download folder as tar.gz">🗜️</button>
+                        <button class="action-btn delete" title="Delete folder">🗑️</button>
                     </div>
                 `;
                 
@@ -78,6 +171,12 @@ document.addEventListener('DOMContentLoaded', () => {
                     downloadFolder(entry.path, entry.name);
                 });
                 
+                const targzBtn = item.querySelector('.action-btn.download-targz');
+                targzBtn.addEventListener('click', (e) => {
+                    e.stopPropagation();
+                    downloadFolder(entry.path, entry.name, 'targz');
+                });
+                
                 // Make folder item clickable for navigation
                 item.addEventListener('click', (e) => {
                     if (e.target === item || e.target.classList.contains('icon')) {
@@ -86,19 +185,31 @@ document.addEventListener('DOMContentLoaded', () => {
                 });
             } else {
                 item.innerHTML = `
-                    <span class="icon file">📄</span>
+                    <input type="checkbox" class="select" title="Select for download">
+                    ${THUMBNAIL_PATTERN.test(entry.name)
+                        ? `<img class="icon thumb" src="${BASE_PATH}/api/thumb?path=${encodeURIComponent(entry.path)}&size=200" alt="" loading="lazy">`
+                        : '<span class="icon file">📄</span>'}
                     <span class="name">${escapeHtml(entry.name)}</span>
                     <span class="size">${formatFileSize(entry.size)}</span>
                     <div class="actions">
                         <button class="action-btn download" title="Download this file">⬇️</button>
+                        <button class="action-btn delete" title="Delete file">🗑️</button>
                     </div>
                 `;
                 
+                // Fall back to the plain icon when there's no preview
+                const thumb = item.querySelector('.thumb');
+                if (thumb) {
+                    thumb.addEventListener('error', () => {
+                        thumb.outerHTML = '<span class="icon file">📄</span>';
+                    });
+                }
+                
                 // Add click event for the file name (open in new tab)
                 const nameEl = item.querySelector('.name');
                 nameEl.style.cursor = 'pointer';
                 nameEl.addEventListener('click', () => {
-                    window.open(`/${entry.path}`, '_blank');
+                    window.open(fileUrl(entry.path), '_blank');
                 });
                 
                 // Add click event for download button
@@ -107,10 +218,46 @@ document.addEventListener('DOMContentLoaded', () => {
                     e.stopPropagation(); // Prevent triggering the parent click event
                     downloadFile(entry.path, entry.name);
                 });
+                
+                // Track ticked files for "Download selected"
+                const checkbox = item.querySelector('.select');
+                checkbox.addEventListener('change', () => {
+                    if (checkbox.checked) {
+                        selectedFiles.add(entry.path);
+                    } else {
+                        selectedFiles.delete(entry.path);
+                    }
+                    updateSelectionButton();
+                });
             }
             
+            // Add click event for delete button
+            const deleteBtn = item.querySelector('.action-btn.delete');
+            deleteBtn.addEventListener('click', (e) => {
+                e.stopPropagation();
+                deleteEntry(entry);
+            });
+            
             fileList.appendChild(item);
         });
+        
+        if (data.has_more) {
+            const moreItem = document.createElement('div');
+            moreItem.className = 'file-item load-more';
+            moreItem.textContent = `Load more (${loaded} of ${data.total} shown)`;
+            moreItem.addEventListener('click', () => {
+                moreItem.textContent = 'Loading...';
+                fetchPage(data.current_path, loaded)
+                    .then(page => {
+                        fileList.removeChild(moreItem);
+                        appendEntries(page, loaded + page.entries.length);
+                    })
+                    .catch(error => {
+                        moreItem.textContent = `Error loading more entries: ${error.message}`;
+                    });
+            });
+            fileList.appendChild(moreItem);
+        }
     };
     
     // Update breadcrumb navigation
@@ -118,13 +265,15 @@ document.addEventListener('DOMContentLoaded', () => {
         breadcrumbs.innerHTML = '<a href="#" data-path="">Root</a>';
         
         if (path) {
-            const parts = path.split('/');
-            let currentPath = '';
+            const raw = path.startsWith(RAW_PATH_PREFIX);
+            const parts = (raw ? path.slice(RAW_PATH_PREFIX.length) : path).split('/');
+            let currentPath = raw ? RAW_PATH_PREFIX : '';
             
             parts.forEach((part, index) => {
                 if (part) {
-                    currentPath += (currentPath ? '/' : '') + part;
-                    breadcrumbs.innerHTML += ` / <a href="#" data-path="${currentPath}">${escapeHtml(part)}</a>`;
+                    currentPath += (currentPath && currentPath !== RAW_PATH_PREFIX ? '/' : '') + part;
+                    const label = raw ? decodeLossy(part) : part;
+                    breadcrumbs.innerHTML += ` / <a href="#" data-path="${escapeHtml(currentPath)}">${escapeHtml(label)}</a>`;
                 }
             });
         }
@@ -144,7 +293,7 @@ document.addEventListener('DOMContentLoaded', () => {
     });
     
     confirmYesBtn.addEventListener('click', () => {
-        fetch('/api/stop', {
+        fetch(`${BASE_PATH}/api/stop`, {
             method: 'POST',
             headers: {
                 'Content-Type': 'application/json'
@@ -178,6 +327,97 @@ document.addEventListener('DOMContentLoaded', () => {
         confirmModal.style.display = 'none';
     });
     
+    // Delete a file or folder after confirmation, then refresh the listing
+    const deleteEntry = (entry) => {
+        const what = entry.is_dir ? `the folder "${entry.name}" and everything in it` : `"${entry.name}"`;
+        if (!confirm(`Delete ${what}? This cannot be undone.`)) return;
+        
+        fetch(`${BASE_PATH}/api/file?path=${encodeURIComponent(entry.path)}`, { method: 'DELETE' })
+            .then(response => response.json())
+            .then(data => {
+                if (!data.success) {
+                    alert('Delete failed: ' + data.message);
+                }
+                loadDirectory(currentPath);
+            })
+            .catch(error => {
+                alert('Error deleting: ' + error.message);
+            });
+    };
+    
+    // Create a folder in the current one, then refresh the listing
+    const createFolder = () => {
+        const name = prompt('Name of the new folder:');
+        if (!name) return;
+        
+        fetch(`${BASE_PATH}/api/mkdir`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ path: currentPath, name })
+        })
+        .then(response => response.json())
+        .then(data => {
+            if (!data.success) {
+                alert('Could not create folder: ' + data.message);
+            }
+            loadDirectory(currentPath);
+        })
+        .catch(error => {
+            alert('Error creating folder: ' + error.message);
+        });
+    };
+    
+    newFolderBtn.addEventListener('click', createFolder);
+    
+    // Upload files into the current folder, then refresh the listing
+    const uploadFiles = (files) => {
+        if (files.length === 0) return;
+        
+        const formData = new FormData();
+        Array.from(files).forEach(file => formData.append('file', file, file.name));
+        
+        dropZone.classList.add('uploading');
+        dropZone.firstChild.textContent = `Uploading ${files.length} file(s)...`;
+        
+        fetch(`${BASE_PATH}/api/upload?path=${encodeURIComponent(currentPath)}`, {
+            method: 'POST',
+            body: formData
+        })
+        .then(response => response.json())
+        .then(data => {
+            if (!data.success) {
+                alert('Upload failed: ' + data.message);
+            }
+            loadDirectory(currentPath);
+        })
+        .catch(error => {
+            alert('Error uploading files: ' + error.message);
+        })
+        .finally(() => {
+            dropZone.classList.remove('uploading');
+            dropZone.firstChild.textContent = 'Drop files here (or click) to upload to this folder';
+            fileInput.value = '';
+        });
+    };
+    
+    dropZone.addEventListener('click', () => fileInput.click());
+    fileInput.addEventListener('change', () => uploadFiles(fileInput.files));
+    
+    dropZone.addEventListener('dragover', (e) => {
+        e.preventDefault();
+        dropZone.classList.add('dragover');
+    });
+    
+    dropZone.addEventListener('dragleave', () => {
+        dropZone.classList.remove('dragover');
+    });
+    
+    dropZone.addEventListener('drop', (e) => {
+        e.preventDefault();
+        dropZone.classList.remove('dragover');
+        uploadFiles(e.dataTransfer.files);
+    });
+    
     // Utility functions
     const formatFileSize = (bytes) => {
         if (bytes === 0) return '0 Bytes';
@@ -187,6 +427,13 @@ document.addEventListener('DOMContentLoaded', () => {
         return parseFloat((bytes / Math.pow(k, i)).toFixed(2)) + ' ' + sizes[i];
     };
     
+    const formatDuration = (seconds) => {
+        if (seconds < 60) return `${seconds}s`;
+        const minutes = Math.floor(seconds / 60);
+        if (minutes < 60) return `${minutes}m ${seconds % 60}s`;
+        return `${Math.floor(minutes / 60)}h ${minutes % 60}m`;
+    };
+    
     const escapeHtml = (unsafe) => {
         return unsafe
             .replace(/&/g, "&amp;")
@@ -200,8 +447,10 @@ document.addEventListener('DOMContentLoaded', () => {
     const downloadFile = (path, filename) => {
         // Create a temporary anchor element
         const anchor = document.createElement('a');
-        anchor.href = `/${path}`;
-        anchor.download = filename; // This attribute triggers download instead of navigation
+        // download=1 makes the server send it as an attachment, so the file
+        // is saved even where the download attribute is ignored
+        anchor.href = `${fileUrl(path)}?download=1`;
+        anchor.download = filename;
         anchor.style.display = 'none';
         document.body.appendChild(anchor);
         
@@ -212,8 +461,39 @@ document.addEventListener('DOMContentLoaded', () => {
         document.body.removeChild(anchor);
     };
     
-    // Function to trigger folder download as zip
-    const downloadFolder = (path, folderName) => {
+    // Completion text for an archive download, noting any files the
+    // server left out because they vanished while it was being built
+    const completeMessage = (skippedFiles) => {
+        if (!skippedFiles) return 'Download complete!';
+        const count = skippedFiles.split(',').length;
+        const files = count === 1 ? '1 file was' : `${count} files were`;
+        return `Download complete, but ${files} left out because they changed or disappeared`;
+    };
+    
+    // Archives at least this big are confirmed before downloading
+    const LARGE_ARCHIVE_BYTES = 1024 * 1024 * 1024;
+    
+    // Look at what a folder's archive would hold and, if it's large, ask
+    // before building it. A failed preview doesn't stop the download
+    const confirmFolderDownload = (path, folderName) =>
+        fetch(`${BASE_PATH}/api/zip/preview?path=${encodeURIComponent(path)}`)
+            .then(response => response.ok ? response.json() : null)
+            .catch(() => null)
+            .then(preview => {
+                if (!preview || preview.total_size < LARGE_ARCHIVE_BYTES) return true;
+                const files = preview.total_files === 1 ? '1 file' : `${preview.total_files} files`;
+                return confirm(`"${folderName}" contains ${files} totalling ${formatFileSize(preview.total_size)}. Download it?`);
+            });
+    
+    // Function to trigger folder download as an archive (zip or targz)
+    const downloadFolder = (path, folderName, format = 'zip') => {
+        confirmFolderDownload(path, folderName).then(confirmed => {
+            if (confirmed) startFolderDownload(path, folderName, format);
+        });
+    };
+    
+    const startFolderDownload = (path, folderName, format) => {
+        const extension = format === 'targz' ? 'tar.gz' : 'zip';
         // Show download status in UI with progress bar
         const downloadStatus = document.createElement('div');
         downloadStatus.className = 'download-status';
@@ -224,14 +504,22 @@ document.addEventListener('DOMContentLoaded', () => {
                 <div class="progress-bar" style="width: 0%"></div>
             </div>
             <p class="progress-text">0%</p>
+            <button class="btn cancel-download">Cancel</button>
         `;
         document.body.appendChild(downloadStatus);
+        const cancelBtn = downloadStatus.querySelector('.cancel-download');
+        cancelBtn.disabled = true;
+        let skippedFiles = null;
         
         // First initialize the ZIP operation to get an operation ID
         console.log(`Initializing ZIP operation for ${path}`);
-        fetch(`/api/zip/init?path=${encodeURIComponent(path)}`)
+        fetch(`${BASE_PATH}/api/zip/init?path=${encodeURIComponent(path)}`)
             .then(response => {
-                if (!response.ok) throw new Error("Failed to initialize zip operation");
+                if (!response.ok) {
+                    return response.json()
+                        .catch(() => ({}))
+                        .then(data => { throw new Error(data.message || "Failed to initialize zip operation"); });
+                }
                 return response.json();
             })
             .then(data => {
@@ -242,15 +530,29 @@ document.addEventListener('DOMContentLoaded', () => {
                 const operationId = data.operationId;
                 console.log(`Got operation ID: ${operationId}`);
                 
+                // Allow cancelling from the UI, and cancel if the user leaves the page
+                activeOperations.add(operationId);
+                cancelBtn.disabled = false;
+                cancelBtn.addEventListener('click', () => {
+                    cancelBtn.disabled = true;
+                    cancelZipOperation(operationId);
+                });
+                
                 // Start progress polling immediately
-                const progressPoller = pollZipProgress(operationId, downloadStatus);
+                const progressPoller = trackZipProgress(operationId, downloadStatus);
                 
                 // Then start the actual download
-                return fetch(`/api/download/folder?path=${encodeURIComponent(path)}&operation_id=${operationId}`)
+                return fetch(`${BASE_PATH}/api/download/folder?path=${encodeURIComponent(path)}&operation_id=${operationId}&format=${format}`)
                     .then(response => {
+                        activeOperations.delete(operationId);
+                        if (response.status === 409) {
+                            if (progressPoller.stop) progressPoller.stop();
+                            throw new Error('Download cancelled');
+                        }
                         if (!response.ok) {
                             throw new Error(`HTTP error! Status: ${response.status}`);
                         }
+                        skippedFiles = response.headers.get('X-Skipped-Files');
                         return response.blob();
                     })
                     .then(blob => {
@@ -258,7 +560,7 @@ document.addEventListener('DOMContentLoaded', () => {
                         if (progressPoller.stop) progressPoller.stop();
                         
                         // Show download is complete
-                        downloadStatus.querySelector('.current-file').textContent = 'Download complete!';
+                        downloadStatus.querySelector('.current-file').textContent = completeMessage(skippedFiles);
                         downloadStatus.querySelector('.progress-bar').style.width = '100%';
                         downloadStatus.querySelector('.progress-text').textContent = '100%';
                         
@@ -268,7 +570,7 @@ document.addEventListener('DOMContentLoaded', () => {
                         // Create and click download link
                         const a = document.createElement('a');
                         a.href = url;
-                        a.download = `${folderName}.zip`;
+                        a.download = `${folderName}.${extension}`;
                         a.style.display = 'none';
                         document.body.appendChild(a);
                         a.click();
@@ -292,63 +594,229 @@ document.addEventListener('DOMContentLoaded', () => {
             });
     };
     
-    // Function to poll for zip creation progress
-    const pollZipProgress = (operationId, statusElement) => {
+    // Zip just the ticked files into one download
+    const downloadSelection = (paths) => {
+        const operationId = `zip_sel_${Date.now()}`;
+        const downloadStatus = document.createElement('div');
+        downloadStatus.className = 'download-status';
+        downloadStatus.innerHTML = `
+            <h4>Downloading ${paths.length} selected file(s)</h4>
+            <p class="current-file">Initializing...</p>
+            <div class="progress-container">
+                <div class="progress-bar" style="width: 0%"></div>
+            </div>
+            <p class="progress-text">0%</p>
+            <button class="btn cancel-download">Cancel</button>
+        `;
+        document.body.appendChild(downloadStatus);
+        const cancelBtn = downloadStatus.querySelector('.cancel-download');
+        cancelBtn.addEventListener('click', () => {
+            cancelBtn.disabled = true;
+            cancelZipOperation(operationId);
+        });
+        
+        activeOperations.add(operationId);
+        const progressPoller = trackZipProgress(operationId, downloadStatus);
+        let skippedFiles = null;
+        
+        fetch(`${BASE_PATH}/api/zip`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ paths, operation_id: operationId })
+        })
+            .then(response => {
+                activeOperations.delete(operationId);
+                progressPoller.stop();
+                if (response.status === 409) {
+                    throw new Error('Download cancelled');
+                }
+                if (!response.ok) {
+                    return response.json()
+                        .catch(() => ({}))
+                        .then(data => { throw new Error(data.message || `HTTP error! Status: ${response.status}`); });
+                }
+                skippedFiles = response.headers.get('X-Skipped-Files');
+                return response.blob();
+            })
+            .then(blob => {
+                downloadStatus.querySelector('.current-file').textContent = completeMessage(skippedFiles);
+                downloadStatus.querySelector('.progress-bar').style.width = '100%';
+                downloadStatus.querySelector('.progress-text').textContent = '100%';
+                
+                const url = window.URL.createObjectURL(blob);
+                const a = document.createElement('a');
+                a.href = url;
+                a.download = 'selection.zip';
+                a.style.display = 'none';
+                document.body.appendChild(a);
+                a.click();
+                window.URL.revokeObjectURL(url);
+                document.body.removeChild(a);
+                
+                setTimeout(() => {
+                    document.body.removeChild(downloadStatus);
+                }, 3000);
+            })
+            .catch(error => {
+                console.error('Download error:', error);
+                downloadStatus.innerHTML = `<p class="error">Error: ${escapeHtml(error.message)}</p>`;
+                setTimeout(() => {
+                    document.body.removeChild(downloadStatus);
+                }, 5000);
+            });
+    };
+    
+    toggleGridBtn.addEventListener('click', () => {
+        setGridView(!fileList.classList.contains('grid'));
+    });
+    
+    downloadSelectedBtn.addEventListener('click', () => {
+        if (selectedFiles.size > 0) {
+            downloadSelection(Array.from(selectedFiles));
+        }
+    });
+    
+    // ZIP operations that are still being built on the server
+    const activeOperations = new Set();
+    
+    // Ask the server to stop building an archive
+    const cancelZipOperation = (operationId) => {
+        return fetch(`${BASE_PATH}/api/zip/cancel?operation_id=${encodeURIComponent(operationId)}`, { method: 'POST' })
+            .catch(error => console.error('Error cancelling ZIP operation:', error));
+    };
+    
+    // Cancel in-progress archives when navigating away
+    window.addEventListener('pagehide', () => {
+        activeOperations.forEach(operationId => {
+            navigator.sendBeacon(`${BASE_PATH}/api/zip/cancel?operation_id=${encodeURIComponent(operationId)}`);
+        });
+    });
+    
+    // Follow zip creation progress: pushed over Server-Sent Events where the
+    // browser supports them, polled otherwise
+    const trackZipProgress = (operationId, statusElement) => {
         const progressBar = statusElement.querySelector('.progress-bar');
         const progressText = statusElement.querySelector('.progress-text');
         const currentFileElement = statusElement.querySelector('.current-file');
         
-        console.log(`Starting progress polling for operation: ${operationId}`);
-        let stopPolling = false;
+        let stopped = false;
+        let events = null;
+        
+        // Render one progress update; returns the percentage shown
+        const showProgress = (data) => {
+            const percentage = Math.min(Math.round(data.percentage), 99);
+            progressBar.style.width = `${percentage}%`;
+            
+            // Format the progress text
+            let statusText = `${percentage}%`;
+            if (data.total_files > 0) {
+                statusText += ` (${data.processed_files}/${data.total_files} files`;
+                if (data.total_bytes > 0) {
+                    statusText += `, ${formatFileSize(data.processed_bytes)} of ${formatFileSize(data.total_bytes)}`;
+                }
+                statusText += ')';
+            }
+            if (data.eta_secs != null) {
+                statusText += ` · ~${formatDuration(data.eta_secs)} remaining`;
+            }
+            progressText.textContent = statusText;
+            
+            // Show current file being processed
+            if (data.current_file) {
+                currentFileElement.textContent = data.current_file;
+            }
+            return percentage;
+        };
         
-        // Function to update the progress bar
-        const updateProgress = () => {
-            if (stopPolling) return;
+        const poll = () => {
+            if (stopped) return;
             
-            fetch(`/api/zip/progress?id=${operationId}`)
+            fetch(`${BASE_PATH}/api/zip/progress?id=${operationId}`)
                 .then(response => response.json())
                 .then(data => {
-                    console.log('Progress update:', data);
-                    
-                    // Update progress UI
-                    const percentage = Math.min(Math.round(data.percentage), 99);
-                    progressBar.style.width = `${percentage}%`;
-                    
-                    // Format the progress text
-                    let statusText = `${percentage}%`;
-                    if (data.total_files > 0) {
-                        statusText += ` (${data.processed_files}/${data.total_files} files)`;
-                    }
-                    progressText.textContent = statusText;
-                    
-                    // Show current file being processed
-                    if (data.current_file) {
-                        currentFileElement.textContent = data.current_file;
-                    }
-                    
                     // Continue polling if not complete
-                    if (percentage < 99 && !stopPolling) {
-                        setTimeout(updateProgress, 300);
+                    if (showProgress(data) < 99 && !stopped) {
+                        setTimeout(poll, 300);
                     }
                 })
                 .catch(error => {
                     console.error('Error checking progress:', error);
                     // Try again unless stopped
-                    if (!stopPolling) {
-                        setTimeout(updateProgress, 1000);
+                    if (!stopped) {
+                        setTimeout(poll, 1000);
                     }
                 });
         };
         
-        // Start polling
-        updateProgress();
+        if (window.EventSource) {
+            events = new EventSource(`${BASE_PATH}/api/zip/events?id=${encodeURIComponent(operationId)}`);
+            events.onmessage = (e) => showProgress(JSON.parse(e.data));
+            events.addEventListener('done', () => events.close());
+            events.onerror = () => {
+                // Proxies that buffer or drop event streams: poll instead
+                events.close();
+                events = null;
+                poll();
+            };
+        } else {
+            poll();
+        }
         
-        // Return an object that can be used to stop polling
+        // Return an object that can be used to stop tracking
         return {
-            stop: () => { stopPolling = true; }
+            stop: () => {
+                stopped = true;
+                if (events) events.close();
+            }
         };
     };
     
+    // Show which build is running, for bug reports
+    const loadVersion = () => {
+        fetch(`${BASE_PATH}/api/version`)
+            .then(response => response.json())
+            .then(data => {
+                const built = new Date(data.built_at * 1000).toLocaleString();
+                const versionEl = document.getElementById('version');
+                versionEl.textContent = `v${data.version} (${data.commit})`;
+                versionEl.title = `Built ${built}`;
+            })
+            .catch(() => {});
+    };
+    
+    // Under --watch, reload the folder being shown when it changes on disk
+    const watchChanges = () => {
+        if (!window.EventSource) {
+            return;
+        }
+        const changes = new EventSource(`${BASE_PATH}/api/watch`);
+        changes.addEventListener('change', (e) => {
+            const { paths } = JSON.parse(e.data);
+            if (paths.includes(currentPath)) {
+                loadDirectory(currentPath);
+            }
+        });
+    };
+    
+    // Hide controls for features the server has turned off
+    const loadConfig = () => {
+        fetch(`${BASE_PATH}/api/config`)
+            .then(response => response.json())
+            .then(data => {
+                document.body.classList.toggle('no-upload', !data.can_upload);
+                document.body.classList.toggle('no-delete', !data.can_delete);
+                document.body.classList.toggle('no-stop', !data.can_stop);
+                document.body.classList.toggle('no-listing', data.can_list === false);
+                document.body.classList.toggle('no-zip', data.can_zip === false);
+                if (data.can_watch) {
+                    watchChanges();
+                }
+            })
+            .catch(() => {});
+    };
+    
     // Initialize the file browser
+    loadConfig();
     loadDirectory();
+    loadVersion();
 });
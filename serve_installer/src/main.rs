@@ -1,47 +1,363 @@
-use std::{fs, io, path::PathBuf};
+use std::{cell::RefCell, fs, io, path::PathBuf, rc::Rc};
 use winreg::enums::*;
 use winreg::RegKey;
 use native_windows_gui as nwg;
 
+// Context-menu keys, relative to a hive's Software\Classes
+const BACKGROUND_MENU_KEY: &str = "Software\\Classes\\Directory\\Background\\shell\\ServeOn8080";
+const FOLDER_MENU_KEY: &str = "Software\\Classes\\Directory\\shell\\ServeOn8080";
+// Optional entry on every file, serving the folder the file is in
+const FILE_MENU_KEY: &str = "Software\\Classes\\*\\shell\\ServeOn8080";
+const MENU_KEYS: [&str; 3] = [BACKGROUND_MENU_KEY, FOLDER_MENU_KEY, FILE_MENU_KEY];
+
+// Machine-wide installs need administrator rights; per-user installs
+// go under the user's profile and HKEY_CURRENT_USER instead.
+#[derive(Clone, Copy)]
+enum Scope {
+    Machine,
+    User,
+}
+
+impl Scope {
+    fn program_dir(self) -> Option<PathBuf> {
+        match self {
+            Scope::Machine => Some(PathBuf::from("C:\\Program Files\\ServeOn8080")),
+            Scope::User => dirs::data_local_dir().map(|dir| dir.join("ServeOn8080")),
+        }
+    }
+
+    fn hive_name(self) -> &'static str {
+        match self {
+            Scope::Machine => "HKEY_LOCAL_MACHINE",
+            Scope::User => "HKEY_CURRENT_USER",
+        }
+    }
+
+    fn hive(self) -> RegKey {
+        match self {
+            Scope::Machine => RegKey::predef(HKEY_LOCAL_MACHINE),
+            Scope::User => RegKey::predef(HKEY_CURRENT_USER),
+        }
+    }
+}
+
+// Choices made in the installer window
+#[derive(Clone, Copy)]
+struct InstallOptions {
+    port: u16,
+    // Also add "Serve this file's folder" to the menu of every file
+    file_menu: bool,
+}
+
+// A registry key the installer creates under the scope's hive, with
+// `data` as its default value
+struct RegistryValue {
+    key: String,
+    data: String,
+}
+
+// Everything an install writes. install_to carries this out and --dry-run
+// only describes it, so the two can't disagree
+struct InstallPlan {
+    scope: Scope,
+    program_dir: PathBuf,
+    source_exe: PathBuf,
+    dest_exe: PathBuf,
+    values: Vec<RegistryValue>,
+}
+
+impl InstallPlan {
+    fn new(scope: Scope, options: InstallOptions) -> io::Result<Self> {
+        let port = options.port;
+        let program_dir = scope.program_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No local app data folder"))?;
+        let source_exe = PathBuf::from("serve_folder.exe");
+        let dest_exe = program_dir.join("serve_folder.exe");
+
+        let mut values = vec![
+            // Background menu
+            RegistryValue {
+                key: BACKGROUND_MENU_KEY.to_string(),
+                data: format!("Host this folder on port {}", port),
+            },
+            RegistryValue {
+                key: format!("{}\\command", BACKGROUND_MENU_KEY),
+                data: format!("\"{}\" \"%V\" --port {}", dest_exe.display(), port),
+            },
+            // Folder menu
+            RegistryValue {
+                key: FOLDER_MENU_KEY.to_string(),
+                data: format!("Host folder on port {}", port),
+            },
+            RegistryValue {
+                key: format!("{}\\command", FOLDER_MENU_KEY),
+                data: format!("\"{}\" \"%1\" --port {}", dest_exe.display(), port),
+            },
+        ];
+        if options.file_menu {
+            // On a file, %W is the folder containing it
+            values.push(RegistryValue {
+                key: FILE_MENU_KEY.to_string(),
+                data: format!("Serve this file's folder on port {}", port),
+            });
+            values.push(RegistryValue {
+                key: format!("{}\\command", FILE_MENU_KEY),
+                data: format!("\"{}\" \"%W\" --port {}", dest_exe.display(), port),
+            });
+        }
+
+        Ok(Self { scope, program_dir, source_exe, dest_exe, values })
+    }
+
+    fn describe(&self) -> String {
+        let mut text = format!("Create folder: {}\n", self.program_dir.display());
+        text.push_str(&format!("Copy file: {} -> {}\n", self.source_exe.display(), self.dest_exe.display()));
+        for value in &self.values {
+            text.push_str(&format!("Set registry value: {}\\{} (Default) = {}\n", self.scope.hive_name(), value.key, value.data));
+        }
+        text
+    }
+}
+
 fn main() -> io::Result<()> {
     nwg::init().expect("Failed to init NWG");
 
-    let program_dir = PathBuf::from("C:\\Program Files\\ServeOn8080");
-    if !program_dir.exists() {
-        fs::create_dir_all(&program_dir)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+
+    let result = if args.iter().any(|arg| arg == "--uninstall") {
+        if dry_run {
+            show_dry_run(&describe_uninstall());
+            Ok(())
+        } else {
+            uninstall()
+        }
+    } else if dry_run {
+        install_dry_run()
+    } else {
+        install()
+    };
+
+    // Report failures in a message box; there is no console to print to
+    if let Err(e) = &result {
+        let message = if e.kind() == io::ErrorKind::PermissionDenied {
+            "Access was denied. Please run the installer as administrator.".to_string()
+        } else {
+            format!("Something went wrong: {}", e)
+        };
+        nwg::error_message("Serve Folder", &message);
+    }
+
+    result
+}
+
+fn install() -> io::Result<()> {
+    // Nothing to do if the user closed the window without choosing a port
+    let options = match prompt_options() {
+        Some(options) => options,
+        None => return Ok(()),
+    };
+
+    match install_to(Scope::Machine, options) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            // Not elevated: offer a per-user install rather than failing
+            if !confirm_user_install() {
+                return Ok(());
+            }
+            install_to(Scope::User, options)?;
+        }
+        result => result?,
+    }
+
+    show_done_window("Install Complete");
+
+    Ok(())
+}
+
+fn install_to(scope: Scope, options: InstallOptions) -> io::Result<()> {
+    let plan = InstallPlan::new(scope, options)?;
+    if !plan.program_dir.exists() {
+        fs::create_dir_all(&plan.program_dir)?;
     }
 
-    let source_exe = PathBuf::from("serve_folder.exe");
-    let dest_exe = program_dir.join("serve_folder.exe");
-    fs::copy(&source_exe, &dest_exe)?;
+    fs::copy(&plan.source_exe, &plan.dest_exe)?;
+
+    let hive = scope.hive();
+    for value in &plan.values {
+        let (key, _) = hive.create_subkey(&value.key)?;
+        key.set_value("", &value.data)?;
+    }
+
+    Ok(())
+}
+
+// Show what install would do with the chosen options, without changing
+// anything. Which scope is used depends on whether the installer runs
+// elevated, so both are listed
+fn install_dry_run() -> io::Result<()> {
+    let options = match prompt_options() {
+        Some(options) => options,
+        None => return Ok(()),
+    };
 
-    let hkcr = RegKey::predef(HKEY_CLASSES_ROOT);
+    let mut text = String::from("Running as administrator, install would:\n");
+    text.push_str(&InstallPlan::new(Scope::Machine, options)?.describe());
+    text.push_str("\nOtherwise, after confirming a per-user install, it would:\n");
+    text.push_str(&InstallPlan::new(Scope::User, options)?.describe());
+    show_dry_run(&text);
 
-    // Background menu
-    let (key, _) = hkcr.create_subkey("Directory\\Background\\shell\\ServeOn8080")?;
-    key.set_value("", &"Host this folder on port 8080")?;
-    let (cmd_key, _) = hkcr.create_subkey("Directory\\Background\\shell\\ServeOn8080\\command")?;
-    cmd_key.set_value("", &format!("\"{}\" \"%V\"", dest_exe.display()))?;
+    Ok(())
+}
 
-    // Folder menu
-    let (key2, _) = hkcr.create_subkey("Directory\\shell\\ServeOn8080")?;
-    key2.set_value("", &"Host folder on port 8080")?;
-    let (cmd_key2, _) = hkcr.create_subkey("Directory\\shell\\ServeOn8080\\command")?;
-    cmd_key2.set_value("", &format!("\"{}\" \"%1\"", dest_exe.display()))?;
+fn confirm_user_install() -> bool {
+    let params = nwg::MessageParams {
+        title: "Administrator rights required",
+        content: "Installing for all users requires running as administrator.\n\nInstall for the current user only instead?",
+        buttons: nwg::MessageButtons::YesNo,
+        icons: nwg::MessageIcons::Question,
+    };
+    nwg::message(&params) == nwg::MessageChoice::Yes
+}
 
-    show_done_window();
+const UNINSTALL_SCOPES: [Scope; 2] = [Scope::User, Scope::Machine];
+
+// Remove the context-menu keys and installed files from both scopes.
+// Anything that is already gone is skipped, so running it twice is harmless.
+fn uninstall() -> io::Result<()> {
+    for scope in UNINSTALL_SCOPES {
+        let hive = scope.hive();
+        for key in MENU_KEYS {
+            ignore_not_found(hive.delete_subkey_all(key))?;
+        }
+
+        if let Some(program_dir) = scope.program_dir() {
+            ignore_not_found(fs::remove_dir_all(program_dir))?;
+        }
+    }
+
+    show_done_window("Uninstall Complete");
 
     Ok(())
 }
 
-fn show_done_window() {
+// What uninstall would remove, in the same order, if present
+fn describe_uninstall() -> String {
+    let mut text = String::from("Uninstall would remove, where present:\n");
+    for scope in UNINSTALL_SCOPES {
+        for key in MENU_KEYS {
+            text.push_str(&format!("Delete registry key: {}\\{}\n", scope.hive_name(), key));
+        }
+        if let Some(program_dir) = scope.program_dir() {
+            text.push_str(&format!("Delete folder: {}\n", program_dir.display()));
+        }
+    }
+    text
+}
+
+fn ignore_not_found(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+// Ask which port the context-menu command should serve on, and whether
+// files get a menu entry too.
+// Returns None if the window is closed without confirming.
+fn prompt_options() -> Option<InstallOptions> {
+    let mut window = nwg::Window::default();
+    let mut label = nwg::Label::default();
+    let mut port_input = nwg::TextInput::default();
+    let mut file_menu_check = nwg::CheckBox::default();
+    let mut install_btn = nwg::Button::default();
+
+    nwg::Window::builder()
+        .size((300, 185))
+        .position((600, 400))
+        .title("Install Serve Folder")
+        .build(&mut window)
+        .unwrap();
+
+    nwg::Label::builder()
+        .text("Port to serve folders on:")
+        .parent(&window)
+        .size((260, 25))
+        .position((20, 15))
+        .build(&mut label)
+        .unwrap();
+
+    nwg::TextInput::builder()
+        .text("8080")
+        .limit(5)
+        .parent(&window)
+        .size((260, 25))
+        .position((20, 45))
+        .build(&mut port_input)
+        .unwrap();
+
+    nwg::CheckBox::builder()
+        .text("Also add \"Serve this file's folder\" to files")
+        .check_state(nwg::CheckBoxState::Unchecked)
+        .parent(&window)
+        .size((260, 25))
+        .position((20, 80))
+        .build(&mut file_menu_check)
+        .unwrap();
+
+    nwg::Button::builder()
+        .text("Install")
+        .parent(&window)
+        .size((80, 30))
+        .position((110, 125))
+        .build(&mut install_btn)
+        .unwrap();
+
+    let chosen = Rc::new(RefCell::new(None));
+    let handler_chosen = chosen.clone();
+    let window_handle = window.handle;
+    let install_handle = install_btn.handle;
+
+    nwg::bind_event_handler(&window.handle, &window.handle, move |evt, _data, handle| {
+        match evt {
+            nwg::Event::OnButtonClick if handle == install_handle => {
+                match port_input.text().trim().parse::<u16>() {
+                    Ok(port) if port != 0 => {
+                        let file_menu = file_menu_check.check_state() == nwg::CheckBoxState::Checked;
+                        *handler_chosen.borrow_mut() = Some(InstallOptions { port, file_menu });
+                        nwg::stop_thread_dispatch();
+                    }
+                    _ => {
+                        nwg::modal_error_message(window_handle, "Invalid port", "Enter a port number between 1 and 65535.");
+                    }
+                }
+            }
+            nwg::Event::OnWindowClose => nwg::stop_thread_dispatch(),
+            _ => {}
+        }
+    });
+
+    window.set_visible(true);
+    nwg::dispatch_thread_events();
+    window.set_visible(false);
+
+    chosen.take()
+}
+
+// Dry runs print the plan for console users and show it in a message box,
+// since the installer usually has no console
+fn show_dry_run(text: &str) {
+    print!("{}", text);
+    nwg::simple_message("Serve Folder (dry run, nothing was changed)", text);
+}
+
+fn show_done_window(title: &str) {
     let mut window = nwg::Window::default();
     let mut done_btn = nwg::Button::default();
 
     nwg::Window::builder()
         .size((300, 120))
         .position((600, 400))
-        .title("Install Complete")
+        .title(title)
         .build(&mut window)
         .unwrap();
 